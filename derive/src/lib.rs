@@ -1,19 +1,169 @@
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, punctuated::Punctuated, Data, DeriveInput, Expr, Field, Fields, Ident, Lit, Meta, Token, Type};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, punctuated::Punctuated, Attribute, Data, DataEnum, DeriveInput, Expr, ExprLit, Field, Fields, Generics, Ident, Lit, Meta, Path, Token, Type, TypeParamBound, Variant};
 
 struct EnvField {
+    krate: Path,
     ident: Ident,
     ty: Type,
+    default: Option<String>,
+    allow_default_secret: bool,
+    duration_unit: Option<String>,
+    default_expr: Option<Expr>,
+    presets: Vec<(String, String)>,
     var_or_file: bool,
-    name: Option<String>
+    raw_file: bool,
+    hex: bool,
+    name: Option<String>,
+    name_from: Option<String>,
+    index_from: Option<String>,
+    aliases: Vec<String>,
+    flag_set: bool,
+    universe: Vec<String>,
+    indexed: bool,
+    trim_chars: Option<String>,
+    percent: bool,
+    json: bool,
+    auto_json: bool,
+    read_timeout: Option<String>,
+    consume: bool,
+    multimap: Option<[String; 3]>,
+    map_separators: Option<(String, String)>,
+    skip: bool,
+    required: bool,
+    default_dir: Option<String>,
+    empty_is_true: bool,
+    wrap: Option<bool>,
+    numbered_from: Option<usize>,
+    intern: bool,
+    compose: Option<(String, Vec<Ident>)>,
+    max_bytes: Option<usize>,
+    line: Option<usize>,
+    min_len_chars: Option<usize>,
+    trim: bool,
+    lowercase: bool,
+    base64_value: bool,
+    blank_as_unset: bool,
+    file_only: bool,
+    allowed: Option<Vec<String>>,
+    min: Option<String>,
+    dedup: bool,
+    sort: bool,
+    max: Option<String>,
+    delimiter: Option<String>,
+    skip_empty: bool,
+    zero_is_none: bool,
+    parse_with: Option<Path>,
+    description: Option<String>,
+    after: Vec<String>
 }
 
-fn handle_field(field: &Field) -> EnvField {
+fn lit_str(value: &Expr) -> Option<String> {
+    if let Expr::Lit(lit) = value {
+        if let Lit::Str(value) = &lit.lit {
+            return Some(value.value());
+        }
+    }
+
+    None
+}
+
+// Bounds for min/max as written, e.g. `1`, `-5`, `0.5` or `"30s"`, they're parsed as the field type
+fn lit_bound(value: &Expr) -> Option<String> {
+    match value {
+        Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) => Some(value.base10_digits().to_string()),
+        Expr::Lit(ExprLit { lit: Lit::Float(value), .. }) => Some(value.base10_digits().to_string()),
+        Expr::Unary(unary) if matches!(unary.op, syn::UnOp::Neg(_)) => lit_bound(&unary.expr).map(|value| format!("-{}", value)),
+        _ => lit_str(value)
+    }
+}
+
+// The T of an Option<T> field
+fn option_inner(ty: &Type) -> Option<&Type> {
+    generic_inner(ty, "Option")
+}
+
+// The T of a `wrapper<T>` type, e.g. the element type of a Vec<T>
+fn generic_inner<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else { return None; };
+    let segment = path.path.segments.last().filter(|segment| segment.ident == wrapper)?;
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None
+        }),
+        _ => None
+    }
+}
+
+// `["a", "b"]`
+fn lit_str_list(value: &Expr) -> Option<Vec<String>> {
+    match value {
+        Expr::Array(values) => values.elems.iter().map(lit_str).collect(),
+        _ => None
+    }
+}
+
+// Accepts both `hook = path::to::fn` and `hook = "path::to::fn"`
+fn lit_path(value: &Expr) -> Option<Path> {
+    match value {
+        Expr::Path(path) => Some(path.path.clone()),
+        _ => lit_str(value).map(|value| syn::parse_str(&value).expect("Expected a path to a function"))
+    }
+}
+
+fn handle_field(field: &Field, krate: &Path) -> EnvField {
     let mut var_or_file = false;
+    let mut raw_file = false;
+    let mut hex = false;
     let mut name: Option<String> = None;
+    let mut default: Option<String> = None;
+    let mut allow_default_secret = false;
+    let mut duration_unit: Option<String> = None;
+    let mut default_expr: Option<Expr> = None;
+    let mut presets: Vec<(String, String)> = Vec::new();
+    let mut name_from: Option<String> = None;
+    let mut index_from: Option<String> = None;
+    let mut aliases: Vec<String> = Vec::new();
+    let mut flag_set = false;
+    let mut indexed = false;
+    let mut trim_chars: Option<String> = None;
+    let mut percent = false;
+    let mut json = false;
+    let mut auto_json = false;
+    let mut read_timeout: Option<String> = None;
+    let mut consume = false;
+    let mut multimap = false;
+    let mut default_dir: Option<String> = None;
+    let mut empty_is_true = false;
+    let mut wrap: Option<bool> = None;
+    let mut numbered_from: Option<usize> = None;
+    let mut intern = false;
+    let mut compose: Option<(String, Vec<Ident>)> = None;
+    let mut max_bytes: Option<usize> = None;
+    let mut line: Option<usize> = None;
+    let mut min_len_chars: Option<usize> = None;
+    let mut trim = false;
+    let mut lowercase = false;
+    let mut base64_value = false;
+    let mut blank_as_unset = false;
+    let mut file_only = false;
+    let mut allowed: Option<Vec<String>> = None;
+    let mut min: Option<String> = None;
+    let mut dedup = false;
+    let mut sort = false;
+    let mut max: Option<String> = None;
+    let mut delimiter: Option<String> = None;
+    let mut skip_empty = false;
+    let mut zero_is_none = false;
+    let mut parse_with: Option<Path> = None;
+    let mut separators: [Option<String>; 3] = [None, None, None];
+    let mut skip = false;
+    let mut required = false;
+    let mut after: Vec<String> = Vec::new();
+    let mut universe: Vec<String> = Vec::new();
 
     for attr in &field.attrs {
         let path = attr.path();
@@ -29,14 +179,303 @@ fn handle_field(field: &Field) -> EnvField {
                         var_or_file = true;
                         continue;
                     }
+
+                    if path.is_ident("allow_default_secret") {
+                        allow_default_secret = true;
+                        continue;
+                    }
+
+                    if path.is_ident("raw_file") {
+                        raw_file = true;
+                        continue;
+                    }
+
+                    if path.is_ident("hex") {
+                        hex = true;
+                        continue;
+                    }
+
+                    if path.is_ident("flag_set") {
+                        flag_set = true;
+                        continue;
+                    }
+
+                    if path.is_ident("indexed") {
+                        indexed = true;
+                        continue;
+                    }
+
+                    if path.is_ident("percent") {
+                        percent = true;
+                        continue;
+                    }
+
+                    // `serde_json` is the same as `json`, for readers who look for the crate name
+                    if path.is_ident("json") || path.is_ident("serde_json") {
+                        json = true;
+                        continue;
+                    }
+
+                    if path.is_ident("auto_json") {
+                        auto_json = true;
+                        continue;
+                    }
+
+                    if path.is_ident("consume") {
+                        consume = true;
+                        continue;
+                    }
+
+                    if path.is_ident("intern") {
+                        intern = true;
+                        continue;
+                    }
+
+                    if path.is_ident("zero_is_none") {
+                        zero_is_none = true;
+                        continue;
+                    }
+
+                    if path.is_ident("skip_empty") {
+                        skip_empty = true;
+                        continue;
+                    }
+
+                    if path.is_ident("empty_is_true") {
+                        empty_is_true = true;
+                        continue;
+                    }
+
+                    if path.is_ident("multimap") {
+                        multimap = true;
+                        continue;
+                    }
+
+                    if path.is_ident("skip") {
+                        skip = true;
+                        continue;
+                    }
+
+                    if path.is_ident("blank_as_unset") {
+                        blank_as_unset = true;
+                        continue;
+                    }
+
+                    if path.is_ident("file_only") {
+                        file_only = true;
+                        continue;
+                    }
+
+                    if path.is_ident("base64_value") {
+                        base64_value = true;
+                        continue;
+                    }
+
+                    if path.is_ident("trim") {
+                        trim = true;
+                        continue;
+                    }
+
+                    if path.is_ident("lowercase") {
+                        lowercase = true;
+                        continue;
+                    }
+
+                    if path.is_ident("dedup") {
+                        dedup = true;
+                        continue;
+                    }
+
+                    if path.is_ident("sort") {
+                        sort = true;
+                        continue;
+                    }
+
+                    if path.is_ident("required") {
+                        required = true;
+                        continue;
+                    }
                 },
                 Meta::NameValue(name_value) => {
-                    if name_value.path.is_ident("name") {
-                        if let Expr::Lit(lit) = name_value.value {
-                            if let Lit::Str(value) = lit.lit {
-                                name = Some(value.value());
-                                continue;
+                    if name_value.path.is_ident("default") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            default = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("duration_unit") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            if !["ms", "s", "m", "h", "d"].contains(&value.as_str()) {
+                                panic!("Expected one of \"ms\", \"s\", \"m\", \"h\" or \"d\" for 'duration_unit'");
                             }
+
+                            duration_unit = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("presets") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            presets = value.split(',')
+                                .map(|preset| preset.split_once('=').expect("Expected presets like \"short=1s,long=30s\""))
+                                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                                .collect();
+                            continue;
+                        }
+                    }
+
+                    // Any expression of the field's type, for defaults that aren't a fixed string
+                    if name_value.path.is_ident("default_expr") {
+                        default_expr = Some(name_value.value);
+                        continue;
+                    }
+
+                    if name_value.path.is_ident("name") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            name = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("alias") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            aliases.push(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("name_from") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            name_from = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("index_from") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            index_from = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("parse_with") {
+                        if let Some(value) = lit_path(&name_value.value) {
+                            parse_with = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("delimiter") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            delimiter = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("trim_chars") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            trim_chars = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("compose") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            compose = Some(parse_template(&value));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("max_bytes") {
+                        if let Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) = &name_value.value {
+                            max_bytes = Some(value.base10_parse().expect("Expected an integer for 'max_bytes'"));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("allowed") {
+                        allowed = Some(lit_str_list(&name_value.value).expect("Expected a list of strings for 'allowed'"));
+                        continue;
+                    }
+
+                    if name_value.path.is_ident("min") {
+                        if let Some(value) = lit_bound(&name_value.value) {
+                            min = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("max") {
+                        if let Some(value) = lit_bound(&name_value.value) {
+                            max = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("min_len_chars") {
+                        if let Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) = &name_value.value {
+                            min_len_chars = Some(value.base10_parse().expect("Expected an integer for 'min_len_chars'"));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("line") {
+                        if let Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) = &name_value.value {
+                            line = Some(value.base10_parse().expect("Expected an integer for 'line'"));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("numbered_from") {
+                        if let Expr::Lit(ExprLit { lit: Lit::Int(value), .. }) = &name_value.value {
+                            numbered_from = Some(value.base10_parse().expect("Expected an integer for 'numbered_from'"));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("overflow") {
+                        match lit_str(&name_value.value).as_deref() {
+                            Some("error") => { continue; },
+                            Some("saturate") => { wrap = Some(false); continue; },
+                            Some("wrap") => { wrap = Some(true); continue; },
+                            _ => {}
+                        }
+                    }
+
+                    if name_value.path.is_ident("default_dir") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            default_dir = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("read_timeout") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            read_timeout = Some(value);
+                            continue;
+                        }
+                    }
+
+                    let separator = ["pair_sep", "entry_sep", "value_sep"].iter().position(|name| name_value.path.is_ident(name));
+                    if let Some(index) = separator {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            separators[index] = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("after") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            after.push(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("universe") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            universe = value.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect();
+                            continue;
                         }
                     }
                 },
@@ -47,68 +486,2140 @@ fn handle_field(field: &Field) -> EnvField {
         }
     }
 
-    EnvField {
+    if default.is_some() && default_expr.is_some() {
+        panic!("'default' and 'default_expr' cannot both be set on a field");
+    }
+
+    if name.is_some() && name_from.is_some() {
+        panic!("'name' and 'name_from' cannot both be set on a field");
+    }
+
+    if index_from.is_some() && (name.is_some() || name_from.is_some()) {
+        panic!("'index_from' cannot be combined with 'name' or 'name_from'");
+    }
+
+    if skip && (var_or_file || name.is_some()) {
+        panic!("'skip' fields aren't read from env, so they can't have 'var_or_file' or 'name'");
+    }
+
+    // Without multimap, pair_sep and entry_sep change how a plain map field like HashMap<String, V> splits
+    if !multimap && separators[2].is_some() {
+        panic!("'value_sep' only applies to 'multimap' fields");
+    }
+
+    let [pair_sep, entry_sep, value_sep] = separators;
+    let map_separators = (!multimap && (pair_sep.is_some() || entry_sep.is_some()))
+        .then(|| (pair_sep.clone().unwrap_or_else(|| String::from("=")), entry_sep.clone().unwrap_or_else(|| String::from(","))));
+    let separators = [pair_sep.unwrap_or_else(|| String::from(":")), entry_sep.unwrap_or_else(|| String::from(";")), value_sep.unwrap_or_else(|| String::from(","))];
+
+    if let Some((_, fields)) = &compose {
+        after.extend(fields.iter().map(Ident::to_string));
+    }
+
+    if file_only && var_or_file {
+        panic!("'file_only' fields are already read from the _FILE var, 'var_or_file' would allow the plain var");
+    }
+
+    if read_timeout.is_some() && !(var_or_file || raw_file) {
+        panic!("'read_timeout' only applies to fields read with 'var_or_file' or 'raw_file'");
+    }
+
+    let field = EnvField {
+        krate: krate.clone(),
         ident: field.ident.clone().unwrap(),
         ty: field.ty.clone(),
+        default,
+        allow_default_secret,
+        duration_unit,
+        default_expr,
+        presets,
         var_or_file,
-        name
+        raw_file,
+        hex,
+        name,
+        name_from,
+        index_from,
+        aliases,
+        flag_set,
+        universe,
+        indexed,
+        trim_chars,
+        percent,
+        json,
+        auto_json,
+        read_timeout,
+        consume,
+        multimap: multimap.then_some(separators),
+        map_separators,
+        skip,
+        required,
+        default_dir,
+        empty_is_true,
+        wrap,
+        numbered_from,
+        intern,
+        compose,
+        max_bytes,
+        line,
+        min_len_chars,
+        trim,
+        lowercase,
+        base64_value,
+        blank_as_unset,
+        file_only,
+        allowed,
+        min,
+        dedup,
+        sort,
+        max,
+        delimiter,
+        skip_empty,
+        zero_is_none,
+        parse_with,
+        description: doc_comment(&field.attrs),
+        after
+    };
+
+    if let [first, second, ..] = field.loaders()[..] {
+        panic!("'{}' and '{}' cannot be combined on a field", first, second);
     }
+
+    field
 }
 
-#[proc_macro_derive(FromEnv, attributes(utils))]
-pub fn derive_env_config(input: TokenStream) -> TokenStream {
-    let ast = parse_macro_input!(input as DeriveInput);
+impl EnvField {
+    // Expression resolving the var name, as a Result<String, EnvError>
+    fn name_quote(&self) -> proc_macro2::TokenStream {
+        let krate = &self.krate;
+        let ident = &self.ident;
 
-    let s = ast.ident;
+        if let Some(name) = &self.name {
+            quote! {
+                Ok::<String, #krate::EnvError>(String::from(#name))
+            }
+        } else if let Some(name_from) = &self.name_from {
+            quote! {
+                #krate::__load::<String>(#name_from, false, false).map(|prefix| #krate::__join_idents(&prefix, stringify!(#ident)))
+            }
+        } else if let Some(index_from) = &self.index_from {
+            // The field's usual name with the index appended, so `shard` with SHARD_INDEX=3 is `SHARD_3`
+            quote! {
+                #krate::__load::<usize>(#index_from, false, false).map(|index| #krate::__join_idents(&#krate::__join_idents(ident, stringify!(#ident)), &index.to_string()))
+            }
+        } else {
+            quote! {
+                Ok::<String, #krate::EnvError>(#krate::__join_idents(ident, stringify!(#ident)))
+            }
+        }
+    }
 
-    let data = match ast.data {
-        Data::Struct(ref data) => data,
-        _ => panic!("FromEnv can only be derived for structs")
-    };
+    // Like name_quote, but with #[utils(alias = "...")] the first of the names that's set wins. Aliases
+    // are joined to the ident like field names are, unless the primary name is given with `name`.
+    // A nested struct is set under a name when any of its vars is.
+    fn load_name_quote(&self) -> proc_macro2::TokenStream {
+        let krate = &self.krate;
+        let name = self.name_quote();
+        if self.aliases.is_empty() {
+            return name;
+        }
 
-    let named_fields = match data.fields {
-        Fields::Named(ref named_fields) => named_fields,
-        _ => panic!("Fields must be named")
-    };
+        let ty = &self.ty;
+        let file = self.reads_file();
+        let aliases = self.alias_quotes();
+        let vars = if self.is_plain() {
+            quote! { |name: &str| <#ty as #krate::FromEnv>::env_vars(name) }
+        } else {
+            quote! { |name: &str| #krate::__source_vars(name, #file) }
+        };
 
-    let fields: Vec<proc_macro2::TokenStream> = named_fields.named.iter()
-    .map(|field| handle_field(field))
-    .map(|field| {
-            let EnvField { ident, ty, .. } = field;
+        quote! {
+            #name.map(|name| #krate::__first_set(name, &[#(#aliases),*], #vars))
+        }
+    }
 
-            let name_quote = if let Some(name) = field.name {
-                quote! {
-                    #name
-                }
+    fn alias_quotes(&self) -> Vec<proc_macro2::TokenStream> {
+        let krate = &self.krate;
+        self.aliases.iter()
+            .map(|alias| if self.name.is_some() {
+                quote! { String::from(#alias) }
             } else {
-                quote! {
-                    &utils::__join_idents(ident, stringify!(#ident))
+                quote! { #krate::__join_idents(ident, #alias) }
+            })
+            .collect()
+    }
+
+    // Expression loading the field from `name`, as a Result<#ty, EnvError>
+    fn loader(&self) -> proc_macro2::TokenStream {
+        let krate = &self.krate;
+        let ty = &self.ty;
+        let loader = self.source_loader();
+
+        let loader = match (&self.default, &self.default_expr) {
+            (Some(default), _) => quote! {
+                #krate::__or_default::<#ty>(&name, #loader, #default)
+            },
+            (None, Some(default_expr)) => quote! {
+                #krate::__or_else::<#ty>(&name, #loader, || #default_expr)
+            },
+            (None, None) => loader
+        };
+
+        // Lists are post-processed in the order parse, dedup, sort
+        let loader = if self.dedup || self.sort {
+            let dedup = self.dedup.then(|| quote! { #krate::__dedup(&mut value); });
+            let sort = self.sort.then(|| quote! { value.sort(); });
+            quote! {
+                #loader.map(|mut value| {
+                    #dedup
+                    #sort
+                    value
+                })
+            }
+        } else {
+            loader
+        };
+
+        // Bounds are checked on the loaded value, defaults included. Unset Option fields aren't checked.
+        let loader = if self.min.is_some() || self.max.is_some() {
+            let min = self.min.as_deref().map_or(quote! { None }, |min| quote! { Some(#min) });
+            let max = self.max.as_deref().map_or(quote! { None }, |max| quote! { Some(#max) });
+            let check = match option_inner(ty) {
+                Some(inner) => quote! {
+                    if let Some(inner) = &value {
+                        #krate::__check_bounds::<#inner>(&name, inner, #min, #max)?;
+                    }
+                },
+                None => quote! {
+                    #krate::__check_bounds::<#ty>(&name, &value, #min, #max)?;
                 }
             };
 
-            if field.var_or_file {
-                quote! {
-                    #ident: <#ty as FromEnv>::load_or_file(#name_quote)?
-                }
-            } else {
+            quote! {
+                #loader.and_then(|value| {
+                    #check
+                    Ok(value)
+                })
+            }
+        } else {
+            loader
+        };
+
+        // Checked like the bounds, against the allowed values parsed as the field type
+        let loader = match &self.allowed {
+            Some(allowed) => {
+                let check = match option_inner(ty) {
+                    Some(inner) => quote! {
+                        if let Some(inner) = &value {
+                            #krate::__check_allowed::<#inner>(&name, inner, &[#(#allowed),*])?;
+                        }
+                    },
+                    None => quote! {
+                        #krate::__check_allowed::<#ty>(&name, &value, &[#(#allowed),*])?;
+                    }
+                };
+
                 quote! {
-                    #ident: <#ty as FromEnv>::load(#name_quote)?
+                    #loader.and_then(|value| {
+                        #check
+                        Ok(value)
+                    })
                 }
+            },
+            None => loader
+        };
+
+        // A blank value reads as unset for everything above, so Option is None and default applies
+        let loader = if self.blank_as_unset {
+            quote! { #krate::__blank_as_unset(|| #loader) }
+        } else {
+            loader
+        };
+
+        // Sourced fields get the var they were loaded from whichever way they were loaded, not only
+        // through Sourced's own load
+        if generic_inner(ty, "Sourced").is_some() {
+            quote! {
+                #loader.map(|mut value| {
+                    value.var = name.clone();
+                    value
+                })
             }
-        })
-        .collect();
-    
-    quote! {
-        impl utils::FromEnv for #s {
-            fn from_env(value: &str) -> Result<Self, utils::EnvErrorType> {
-                Err(utils::EnvErrorType::Other(String::from("'from' method not implemented for derive(FromEnv)")))
+        } else if option_inner(ty).and_then(|inner| generic_inner(inner, "Sourced")).is_some() {
+            quote! {
+                #loader.map(|mut value| {
+                    if let Some(value) = &mut value {
+                        value.var = name.clone();
+                    }
+                    value
+                })
             }
+        } else {
+            loader
+        }
+    }
+
+    fn source_loader(&self) -> proc_macro2::TokenStream {
+        let krate = &self.krate;
+        let ty = &self.ty;
 
-            fn load(ident: &str) -> Result<Self, utils::EnvError> {
-                Ok(#s {
-                    #(#fields),*
+        if self.indexed {
+            quote! {
+                #krate::__load_indexed(&name)
+            }
+        } else if let Some(start) = self.numbered_from {
+            quote! {
+                #krate::__load_numbered(&name, #start)
+            }
+        } else if self.flag_set {
+            let file = self.var_or_file;
+            let universe = &self.universe;
+            quote! {
+                #krate::__load_flag_set(&name, #file, &[#(#universe),*])
+            }
+        } else if let Some((pair, entry)) = &self.map_separators {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_with::<#ty>(&name, #file, #raw, |value| #krate::__parse_map(value, #pair, #entry))
+            }
+        } else if let Some([pair, entry, value]) = &self.multimap {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_multimap(&name, #file, #raw, #pair, #entry, #value)
+            }
+        } else if let Some((template, fields)) = &self.compose {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            let bindings = fields.iter().map(|field| format_ident!("__field_{}", field));
+            quote! {
+                #krate::__load_or::<#ty>(&name, #file, #raw, || format!(#template, #(&#bindings),*))
+            }
+        } else if self.intern {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_interned::<#ty>(&name, #file, #raw)
+            }
+        } else if self.zero_is_none {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_zero_is_none(&name, #file, #raw)
+            }
+        } else if self.empty_is_true {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_flag(&name, #file, #raw)
+            }
+        } else if let Some(subpath) = &self.default_dir {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_default_dir(&name, #file, #raw, #subpath)
+            }
+        } else if let Some(parse_with) = &self.parse_with {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_parsed::<#ty>(&name, #file, #raw, #parse_with)
+            }
+        } else if self.delimiter.is_some() || self.skip_empty {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            let delimiter = self.delimiter.as_deref().unwrap_or(",");
+            let skip_empty = self.skip_empty;
+            quote! {
+                #krate::__load_with::<#ty>(&name, #file, #raw, |value| #krate::__parse_list(value, #delimiter, #skip_empty))
+            }
+        } else if self.json {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            match option_inner(ty) {
+                Some(inner) => quote! {
+                    #krate::__load_json_opt::<#inner>(&name, #file, #raw)
+                },
+                None => quote! {
+                    #krate::__load_json::<#ty>(&name, #file, #raw)
+                }
+            }
+        } else if self.auto_json {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_auto_json(&name, #file, #raw)
+            }
+        } else if self.hex {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_hex::<#ty>(&name, #file, #raw)
+            }
+        } else if self.has_transforms() {
+            let file = self.reads_file();
+            let raw = self.raw_file;
+            let transforms = self.transforms();
+            quote! {
+                #krate::__load_with::<#ty>(&name, #file, #raw, |value| {
+                    #(#transforms)*
+                    <#ty as #krate::FromEnv>::from_env(value)
                 })
             }
+        } else if let Some(timeout) = &self.read_timeout {
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_timeout::<#ty>(&name, #raw, #timeout)
+            }
+        } else if self.file_only {
+            let raw = self.raw_file;
+            quote! {
+                #krate::__load_file_only::<#ty>(&name, #raw)
+            }
+        } else if self.raw_file {
+            quote! {
+                #krate::__load::<#ty>(&name, true, true)
+            }
+        } else if self.var_or_file {
+            quote! {
+                <#ty as #krate::FromEnv>::load_or_file(&name)
+            }
+        } else {
+            quote! {
+                <#ty as #krate::FromEnv>::load(&name)
+            }
+        }
+    }
+
+    // Statements rebinding `value: &str` before it's parsed
+    fn transforms(&self) -> Vec<proc_macro2::TokenStream> {
+        let krate = &self.krate;
+        let mut transforms = Vec::new();
+
+        // Checked before anything else touches the value
+        if let Some(max_bytes) = self.max_bytes {
+            transforms.push(quote! {
+                if value.len() > #max_bytes {
+                    return Err(#krate::EnvErrorType::InvalidFormat);
+                }
+            });
+        }
+
+        // The whole value is encoded, so it's decoded before anything looks inside it
+        if self.base64_value {
+            transforms.push(quote! {
+                let value = #krate::__base64_value(value)?;
+                let value = value.as_str();
+            });
+        }
+
+        if let Some(line) = self.line {
+            transforms.push(quote! {
+                let value = #krate::__line(value, #line)?;
+            });
+        }
+
+        if let Some(chars) = &self.trim_chars {
+            transforms.push(quote! {
+                let value = value.trim_matches(|ch| #chars.contains(ch));
+            });
+        }
+
+        // trim and lowercase normalize the raw string (file contents included), so on a Masked<T> they
+        // apply to the string T is parsed from
+        if self.trim {
+            transforms.push(quote! {
+                let value = value.trim();
+            });
+        }
+
+        if self.lowercase {
+            transforms.push(quote! {
+                let value = value.to_lowercase();
+                let value = value.as_str();
+            });
+        }
+
+        // Checked on the raw value, so the error never has to mention a (possibly secret) parsed value
+        if let Some(min_len_chars) = self.min_len_chars {
+            transforms.push(quote! {
+                if value.chars().count() < #min_len_chars {
+                    return Err(#krate::EnvErrorType::Other(format!("Must be at least {} characters", #min_len_chars)));
+                }
+            });
+        }
+
+        if self.percent {
+            transforms.push(quote! {
+                let value = #krate::__percent(value)?;
+                let value = value.as_str();
+            });
+        }
+
+        if let Some(wrap) = self.wrap {
+            let ty = &self.ty;
+            transforms.push(quote! {
+                let value = #krate::__overflow::<#ty>(value, #wrap)?;
+                let value = value.as_str();
+            });
+        }
+
+        // A preset name (in any case) stands in for its value, anything else is parsed as written
+        if !self.presets.is_empty() {
+            let (names, values): (Vec<&String>, Vec<&String>) = self.presets.iter().map(|(name, value)| (name, value)).unzip();
+            transforms.push(quote! {
+                let value = #krate::__preset(value, &[#((#names, #values)),*]);
+            });
+        }
+
+        // A bare number is in the given unit rather than seconds, an explicit unit still wins
+        if let Some(unit) = &self.duration_unit {
+            transforms.push(quote! {
+                let value = #krate::__duration_unit(value, #unit);
+                let value = value.as_str();
+            });
+        }
+
+        transforms
+    }
+
+    // Attributes that each pick their own way of loading the field, at most one can be set
+    fn loaders(&self) -> Vec<&'static str> {
+        let loaders = [
+            ("indexed", self.indexed),
+            ("numbered_from", self.numbered_from.is_some()),
+            ("flag_set", self.flag_set),
+            ("multimap", self.multimap.is_some()),
+            ("pair_sep or entry_sep", self.map_separators.is_some()),
+            ("compose", self.compose.is_some()),
+            ("intern", self.intern),
+            ("empty_is_true", self.empty_is_true),
+            ("zero_is_none", self.zero_is_none),
+            ("default_dir", self.default_dir.is_some()),
+            ("parse_with", self.parse_with.is_some()),
+            ("delimiter or skip_empty", self.delimiter.is_some() || self.skip_empty),
+            ("json", self.json),
+            ("auto_json", self.auto_json),
+            ("hex", self.hex),
+            ("base64_value, trim, lowercase, presets, duration_unit, trim_chars, percent, overflow, max_bytes, line or min_len_chars", self.has_transforms()),
+            ("read_timeout", self.read_timeout.is_some()),
+            ("file_only", self.file_only)
+        ];
+
+        loaders.iter().filter(|(_, set)| *set).map(|(name, _)| *name).collect()
+    }
+
+    fn has_transforms(&self) -> bool {
+        !self.transforms().is_empty()
+    }
+
+    // Local the field is loaded into before the struct is assembled
+    fn binding(&self) -> Ident {
+        format_ident!("__field_{}", self.ident)
+    }
+
+    fn binding_stmt(&self, consume: bool) -> proc_macro2::TokenStream {
+        let krate = &self.krate;
+        let binding = self.binding();
+        let name = self.load_name_quote();
+        let loader = self.loader();
+        let consume = if consume && self.consume {
+            let file = self.reads_file();
+            quote! { #krate::__consume(&name, #file); }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            let #binding = {
+                let name = #name?;
+                let value = #loader?;
+                #consume
+                value
+            };
+        }
+    }
+
+    // Loaders that don't go through FromEnv, so the field's type may not implement it
+    fn bypasses_from_env(&self) -> bool {
+        self.json || self.parse_with.is_some() || self.hex || self.intern || self.flag_set || self.multimap.is_some()
+    }
+
+    fn has_default(&self) -> bool {
+        self.default.is_some() || self.default_expr.is_some()
+    }
+
+    fn reads_file(&self) -> bool {
+        self.var_or_file || self.raw_file || self.file_only
+    }
+
+    // Plain fields go through FromEnv::load, so nested structs can recurse through the trait
+    fn is_plain(&self) -> bool {
+        self.loaders().is_empty() && !(self.raw_file || self.var_or_file || self.has_default() || self.min.is_some() || self.max.is_some() || self.dedup || self.sort || self.blank_as_unset || self.allowed.is_some())
+    }
+}
+
+// Turns `http://{self.host}:{self.port}` into a format string and the fields it references
+fn parse_template(template: &str) -> (String, Vec<Ident>) {
+    let mut format = String::new();
+    let mut fields = Vec::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); format.push_str("{{"); },
+            '}' if chars.peek() == Some(&'}') => { chars.next(); format.push_str("}}"); },
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|ch| *ch != '}').collect();
+                let field = placeholder.trim().strip_prefix("self.").expect("Expected placeholders like '{self.field}' in 'compose'");
+                fields.push(format_ident!("{}", field));
+                format.push_str("{}");
+            },
+            '}' => panic!("Unmatched '}}' in 'compose'"),
+            _ => format.push(ch)
+        }
+    }
+
+    (format, fields)
+}
+
+// Backs #[utils(deny_unmarked)], every field has to say whether it's optional or required
+fn check_marked(fields: &[EnvField]) -> Result<(), syn::Error> {
+    for field in fields {
+        if !(option_inner(&field.ty).is_some() || field.has_default() || field.required) {
+            return Err(syn::Error::new(field.ident.span(), format!("Field '{}' needs to be an Option, have a 'default' or be marked #[utils(required)]", field.ident)));
+        }
+    }
+
+    Ok(())
+}
+
+// Declaration order, except that fields marked #[utils(after = "...")] wait for the fields they name
+fn load_order(fields: &[EnvField]) -> Result<Vec<usize>, syn::Error> {
+    for field in fields {
+        for dependency in &field.after {
+            if !fields.iter().any(|other| other.ident == dependency) {
+                return Err(syn::Error::new(field.ident.span(), format!("'{}' is loaded after unknown field '{}'", field.ident, dependency)));
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = Vec::with_capacity(fields.len());
+    while order.len() < fields.len() {
+        let next = (0..fields.len()).find(|index| {
+            !order.contains(index) && fields[*index].after.iter().all(|dependency| {
+                order.iter().any(|loaded| fields[*loaded].ident == dependency)
+            })
+        });
+
+        match next {
+            Some(index) => order.push(index),
+            None => {
+                let field = (0..fields.len()).find(|index| !order.contains(index)).map(|index| &fields[index].ident).unwrap();
+                return Err(syn::Error::new(field.span(), format!("Cyclic 'after' dependency involving field '{}'", field)));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+// Types that hold a secret, looked for through an Option
+fn is_secret_type(ty: &Type) -> bool {
+    let Type::Path(path) = ty else { return false; };
+    let Some(segment) = path.path.segments.last() else { return false; };
+    if segment.ident == "Option" {
+        if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+            if let Some(syn::GenericArgument::Type(ty)) = args.args.first() {
+                return is_secret_type(ty);
+            }
+        }
+    }
+
+    ["Masked", "SecretString", "SecretBox", "SecretSlice", "Zeroizing"].iter().any(|name| segment.ident == name)
+}
+
+// A secret shouldn't ship with a hardcoded default, unless the field opts in with #[utils(allow_default_secret)]
+fn check_default_secrets(fields: &[EnvField], container: &EnvContainer) -> Result<(), syn::Error> {
+    for field in fields {
+        if field.has_default() && !field.allow_default_secret && (container.secret || is_secret_type(&field.ty)) {
+            return Err(syn::Error::new(field.ident.span(), format!("Secret field '{}' can't have a 'default', mark it #[utils(allow_default_secret)] if that's intended", field.ident)));
+        }
+    }
+
+    Ok(())
+}
+
+// Whether `bound` fits the primitive number type `ty` is, if it's one at all
+fn bound_fits(ty: &Type, bound: &str) -> Option<bool> {
+    let Type::Path(path) = ty else { return None; };
+    let name = path.path.get_ident()?.to_string();
+    let fits = match name.as_str() {
+        "u8" => bound.parse::<u8>().is_ok(),
+        "u16" => bound.parse::<u16>().is_ok(),
+        "u32" => bound.parse::<u32>().is_ok(),
+        "u64" => bound.parse::<u64>().is_ok(),
+        "u128" => bound.parse::<u128>().is_ok(),
+        "usize" => bound.parse::<usize>().is_ok(),
+        "i8" => bound.parse::<i8>().is_ok(),
+        "i16" => bound.parse::<i16>().is_ok(),
+        "i32" => bound.parse::<i32>().is_ok(),
+        "i64" => bound.parse::<i64>().is_ok(),
+        "i128" => bound.parse::<i128>().is_ok(),
+        "isize" => bound.parse::<isize>().is_ok(),
+        "f32" => bound.parse::<f32>().is_ok_and(f32::is_finite),
+        "f64" => bound.parse::<f64>().is_ok_and(f64::is_finite),
+        _ => return None
+    };
+
+    Some(fits)
+}
+
+// A min/max that can't be a value of the field's number type (e.g. `max = 70000` on a u16) is
+// a typo, so it fails the build rather than every load. Other types still parse theirs on load.
+fn check_bounds(fields: &[EnvField]) -> Result<(), syn::Error> {
+    for field in fields {
+        let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+        for (attr, bound) in [("min", &field.min), ("max", &field.max)] {
+            let Some(bound) = bound else { continue; };
+            if bound_fits(ty, bound) == Some(false) {
+                return Err(syn::Error::new(field.ident.span(), format!("'{} = {}' on field '{}' isn't a valid {}", attr, bound, field.ident, quote!(#ty))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct EnvContainer {
+    diff: bool,
+    redact: bool,
+    secret: bool,
+    to_json: bool,
+    deny_unmarked: bool,
+    case_insensitive: bool,
+    builder: bool,
+    validate: bool,
+    env_names: bool,
+    reload: bool,
+    tag: Option<String>,
+    blob_var: Option<String>,
+    prefix: Option<String>,
+    separator: Option<String>,
+    explain: bool,
+    instances: bool,
+    rename_all: Option<String>,
+    finalize: Option<Path>,
+    blob_separators: (String, String),
+    // Some(true) makes a secret-looking field that isn't masked an error rather than a warning
+    check_secrets: Option<bool>,
+    secret_patterns: Option<Vec<String>>,
+    // Generated code names everything through this path, `utils` unless #[utils(crate = "...")] says otherwise
+    krate: Path,
+    global: bool,
+    from_bool: bool
+}
+
+// Doc comment lines joined by spaces, used as the description in generated schemas
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs.iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(name_value) => lit_str(&name_value.value),
+            _ => None
+        })
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+// Reads `#[serde(key = "...")]` so enums already renamed for serde don't need the same renames
+// twice. Only with the serde feature, and #[utils(...)] renames always take precedence.
+#[cfg(feature = "serde")]
+fn serde_name(attrs: &[Attribute], key: &str) -> Option<String> {
+    attrs.iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+        .filter_map(|attr| attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated).ok())
+        .flatten()
+        .find_map(|arg| match arg {
+            Meta::NameValue(name_value) if name_value.path.is_ident(key) => lit_str(&name_value.value),
+            _ => None
+        })
+}
+
+#[cfg(not(feature = "serde"))]
+fn serde_name(_attrs: &[Attribute], _key: &str) -> Option<String> {
+    None
+}
+
+// The rename_all rules serde supports, applied to a PascalCase variant name or a snake_case field name
+fn rename_case(name: &str, rule: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    for ch in name.chars() {
+        if ch == '_' {
+            words.push(String::new());
+            continue;
+        }
+
+        if ch.is_uppercase() || words.is_empty() {
+            words.push(String::new());
+        }
+        words.last_mut().unwrap().push(ch);
+    }
+
+    words.retain(|word| !word.is_empty());
+    let lower: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+    let upper: Vec<String> = words.iter().map(|word| word.to_uppercase()).collect();
+    let capitalized: Vec<String> = lower.iter()
+        .map(|word| word.chars().take(1).flat_map(char::to_uppercase).chain(word.chars().skip(1)).collect())
+        .collect();
+    match rule {
+        "lowercase" => name.to_lowercase(),
+        "UPPERCASE" => name.to_uppercase(),
+        "PascalCase" => capitalized.concat(),
+        "camelCase" => lower[0].clone() + &capitalized[1..].concat(),
+        "snake_case" => lower.join("_"),
+        "SCREAMING_SNAKE_CASE" => upper.join("_"),
+        "kebab-case" => lower.join("-"),
+        "SCREAMING-KEBAB-CASE" => upper.join("-"),
+        _ => panic!("Unknown 'rename_all' rule '{}'", rule)
+    }
+}
+
+// Name a unit variant is matched by: #[utils(rename)], then serde's rename, then the rename_all rule
+fn variant_name(variant: &Variant, rename_all: Option<&str>) -> String {
+    let mut rename: Option<String> = None;
+    for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("utils")) {
+        let args = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated).expect("Error parsing arguments to 'utils' attribute");
+        for arg in args {
+            match arg {
+                Meta::NameValue(name_value) if name_value.path.is_ident("rename") => {
+                    rename = Some(lit_str(&name_value.value).expect("Expected a string for 'rename'"));
+                },
+                // Read by variant_flag
+                Meta::Path(path) if path.is_ident("truthy") || path.is_ident("falsey") => {},
+                _ => panic!("Encountered unknown or invalid arguments in 'utils' attribute")
+            }
+        }
+    }
+
+    let name = variant.ident.to_string();
+    rename
+        .or_else(|| serde_name(&variant.attrs, "rename"))
+        .unwrap_or_else(|| rename_all.map_or(name.clone(), |rule| rename_case(&name, rule)))
+}
+
+// Whether a variant has a bare `#[utils(flag)]`, e.g. `truthy`
+fn variant_flag(variant: &Variant, flag: &str) -> bool {
+    variant.attrs.iter()
+        .filter(|attr| attr.path().is_ident("utils"))
+        .filter_map(|attr| attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated).ok())
+        .flatten()
+        .any(|arg| matches!(arg, Meta::Path(path) if path.is_ident(flag)))
+}
+
+fn handle_container(attrs: &[Attribute]) -> EnvContainer {
+    let mut diff = false;
+    let mut redact = false;
+    let mut secret = false;
+    let mut to_json = false;
+    let mut deny_unmarked = false;
+    let mut case_insensitive = false;
+    let mut builder = false;
+    let mut validate = false;
+    let mut env_names = false;
+    let mut reload = false;
+    let mut tag: Option<String> = None;
+    let mut blob_var: Option<String> = None;
+    let mut prefix: Option<String> = None;
+    let mut explain = false;
+    let mut instances = false;
+    let mut rename_all: Option<String> = None;
+    let mut finalize: Option<Path> = None;
+    let mut separator: Option<String> = None;
+    let mut blob_separators = (String::from("="), String::from(";"));
+    let mut check_secrets: Option<bool> = None;
+    let mut secret_patterns: Option<Vec<String>> = None;
+    let mut krate: Option<Path> = None;
+    let mut global = false;
+    let mut from_bool = false;
+
+    for attr in attrs {
+        let path = attr.path();
+        if !path.is_ident("utils") {
+            continue;
+        }
+
+        let args = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated).expect("Error parsing arguments to 'utils' attribute");
+        for arg in args {
+            match arg {
+                Meta::Path(path) => {
+                    if path.is_ident("diff") {
+                        diff = true;
+                        continue;
+                    }
+
+                    if path.is_ident("redact") {
+                        redact = true;
+                        continue;
+                    }
+
+                    if path.is_ident("secret") {
+                        secret = true;
+                        continue;
+                    }
+
+                    if path.is_ident("to_json") {
+                        if !cfg!(feature = "serde") {
+                            panic!("'to_json' requires the serde feature");
+                        }
+
+                        to_json = true;
+                        continue;
+                    }
+
+                    if path.is_ident("deny_unmarked") {
+                        deny_unmarked = true;
+                        continue;
+                    }
+
+                    if path.is_ident("case_insensitive") {
+                        case_insensitive = true;
+                        continue;
+                    }
+
+                    if path.is_ident("builder") {
+                        builder = true;
+                        continue;
+                    }
+
+                    if path.is_ident("validate") {
+                        validate = true;
+                        continue;
+                    }
+
+                    if path.is_ident("env_names") {
+                        env_names = true;
+                        continue;
+                    }
+
+                    if path.is_ident("global") {
+                        global = true;
+                        continue;
+                    }
+
+                    if path.is_ident("from_bool") {
+                        from_bool = true;
+                        continue;
+                    }
+
+                    if path.is_ident("explain") {
+                        explain = true;
+                        continue;
+                    }
+
+                    if path.is_ident("instances") {
+                        instances = true;
+                        continue;
+                    }
+
+                    if path.is_ident("reload") {
+                        reload = true;
+                        continue;
+                    }
+
+                    if path.is_ident("check_secrets") {
+                        check_secrets = Some(false);
+                        continue;
+                    }
+                },
+                Meta::NameValue(name_value) => {
+                    if name_value.path.is_ident("tag") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            tag = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("rename_all") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            rename_all = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("prefix") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            prefix = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("pair_sep") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            blob_separators.0 = value;
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("entry_sep") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            blob_separators.1 = value;
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("separator") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            separator = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("check_secrets") {
+                        match lit_str(&name_value.value).as_deref() {
+                            Some("warn") => { check_secrets = Some(false); continue; },
+                            Some("error") => { check_secrets = Some(true); continue; },
+                            _ => panic!("Expected \"warn\" or \"error\" for 'check_secrets'")
+                        }
+                    }
+
+                    // Like #[serde(crate = "...")], for crates that re-export this one under another name
+                    if name_value.path.is_ident("crate") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            krate = Some(syn::parse_str(&value).expect("Expected a path for 'crate'"));
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("secret_patterns") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            secret_patterns = Some(value.split(',').map(|pattern| pattern.trim().to_lowercase()).collect());
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("blob_var") {
+                        if let Some(value) = lit_str(&name_value.value) {
+                            blob_var = Some(value);
+                            continue;
+                        }
+                    }
+
+                    if name_value.path.is_ident("finalize") {
+                        if let Some(value) = lit_path(&name_value.value) {
+                            finalize = Some(value);
+                            continue;
+                        }
+                    }
+                },
+                _ => {}
+            }
+
+            panic!("Encountered unknown or invalid arguments in 'utils' attribute");
+        }
+    }
+
+    if secret_patterns.is_some() && check_secrets.is_none() {
+        panic!("'secret_patterns' only applies with 'check_secrets'");
+    }
+
+    EnvContainer {
+        diff,
+        redact,
+        secret,
+        to_json,
+        deny_unmarked,
+        case_insensitive,
+        builder,
+        validate,
+        env_names,
+        reload,
+        tag,
+        blob_var,
+        prefix,
+        separator,
+        explain,
+        instances,
+        rename_all: rename_all.or_else(|| serde_name(attrs, "rename_all")),
+        finalize,
+        blob_separators,
+        check_secrets,
+        secret_patterns,
+        krate: krate.unwrap_or_else(|| parse_quote!(utils)),
+        global,
+        from_bool
+    }
+}
+
+fn bounded_generics(generics: &Generics, bound: TypeParamBound) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(bound.clone());
+    }
+
+    generics
+}
+
+// Enums pick the variant by presence: each variant wraps a struct loaded under `{ident}_{Variant}`,
+// and the one variant with any of its vars set is loaded. Several set is ambiguous and an error.
+// With #[utils(tag = "VAR")] the variant is instead named by that var, in any case.
+fn derive_enum(s: &Ident, generics: &Generics, data: &DataEnum, container: &EnvContainer) -> TokenStream {
+    let krate = &container.krate;
+    let tag = container.tag.as_deref();
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    // #[utils(from_bool)] maps anything bool accepts (true, yes, on, ...) to the #[utils(truthy)]
+    // and #[utils(falsey)] variants of a two variant enum
+    if container.from_bool {
+        let find = |flag: &str| {
+            let mut variants = data.variants.iter().filter(|variant| variant_flag(variant, flag));
+            match (variants.next(), variants.next()) {
+                (Some(variant), None) if matches!(variant.fields, Fields::Unit) => &variant.ident,
+                _ => panic!("'from_bool' needs exactly one unit variant marked #[utils({})]", flag)
+            }
+        };
+
+        if data.variants.len() != 2 {
+            panic!("'from_bool' can only be used on enums with two variants");
+        }
+
+        let (truthy, falsey) = (find("truthy"), find("falsey"));
+        return quote! {
+            impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+                fn from_env(value: &str) -> Result<Self, #krate::EnvErrorType> {
+                    match <bool as #krate::FromEnv>::from_env(value) {
+                        Ok(true) => Ok(#s::#truthy),
+                        Ok(false) => Ok(#s::#falsey),
+                        Err(_) => Err(#krate::EnvErrorType::InvalidFormat)
+                    }
+                }
+
+                fn type_name() -> &'static str {
+                    stringify!(#s)
+                }
+            }
+        }.into();
+    }
+
+    // Enums of unit variants are plain values instead, matched by variant name in any case. Anything
+    // else is an error listing the names that would have matched, or with a `Custom(String)` variant
+    // marked #[utils(other)] it's kept there, for vocabularies that grow after a binary ships. The
+    // variant can wrap any FromEnv type (e.g. `Fixed(T)`), values it can't parse get the same error.
+    let (others, known): (Vec<&Variant>, Vec<&Variant>) = data.variants.iter().partition(|variant| variant_flag(variant, "other"));
+    if let [_, second, ..] = &others[..] {
+        return syn::Error::new(second.ident.span(), "Only one variant can be marked #[utils(other)]").to_compile_error().into();
+    }
+
+    if let Some(other) = others.first() {
+        if !matches!(&other.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1) {
+            return syn::Error::new(other.ident.span(), "The #[utils(other)] variant has to wrap a single value").to_compile_error().into();
+        }
+    }
+
+    if known.iter().all(|variant| matches!(variant.fields, Fields::Unit)) {
+        let names: Vec<String> = known.iter()
+            .map(|variant| variant_name(variant, container.rename_all.as_deref()))
+            .collect();
+        let expected = format!("one of {}", names.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>().join(", "));
+        let matches = known.iter().zip(&names).map(|(variant, name)| {
+            let ident = &variant.ident;
+            quote! {
+                if value.eq_ignore_ascii_case(#name) {
+                    return Ok(#s::#ident);
+                }
+            }
+        });
+
+        let unknown = quote! { #krate::EnvErrorType::InvalidFormat };
+        let fallback = match others.first().map(|other| (&other.ident, &other.fields)) {
+            Some((ident, Fields::Unnamed(fields))) => {
+                let ty = &fields.unnamed[0].ty;
+                quote! { <#ty as #krate::FromEnv>::from_env(value).map(#s::#ident).map_err(|_| #unknown) }
+            },
+            _ => quote! { Err(#unknown) }
+        };
+
+        return quote! {
+            impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+                fn from_env(value: &str) -> Result<Self, #krate::EnvErrorType> {
+                    let value = value.trim();
+                    #(#matches)*
+                    #fallback
+                }
+
+                fn type_name() -> &'static str {
+                    stringify!(#s)
+                }
+
+                fn expected() -> &'static str {
+                    #expected
+                }
+            }
+        }.into();
+    }
+
+    let variants: Vec<(&Ident, &Type)> = data.variants.iter()
+        .map(|variant| match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => (&variant.ident, &fields.unnamed[0].ty),
+            _ => panic!("Enum variants must wrap a single struct")
+        })
+        .collect();
+
+    let names: Vec<String> = variants.iter().map(|(variant, _)| variant.to_string()).collect();
+    let detects = variants.iter().map(|(variant, ty)| {
+        let name = variant.to_string();
+        quote! {
+            let present = <#ty as #krate::FromEnv>::env_vars(&#krate::__join_idents(ident, #name)).iter().any(|var| #krate::__is_set(var));
+            if present {
+                if let Some(other) = found {
+                    return Err(#krate::EnvError { var: String::from(ident), ty: #krate::EnvErrorType::Other(format!("Both '{}' and '{}' are configured", other, #name)), expected: None, value: None });
+                }
+
+                found = Some(#name);
+            }
+        }
+    });
+
+    let select = match tag {
+        Some(tag) => quote! {
+            let tag = #krate::__load::<String>(#tag, false, false)?;
+            let found = [#(#names),*].into_iter().find(|name| name.eq_ignore_ascii_case(tag.trim()));
+            if found.is_none() {
+                return Err(#krate::EnvError { var: String::from(#tag), ty: #krate::EnvErrorType::InvalidFormat, expected: Some(stringify!(#s)), value: None });
+            }
+        },
+        None => quote! {
+            let mut found: Option<&'static str> = None;
+            #(#detects)*
+        }
+    };
+
+    let tag_var = tag.map(|tag| quote! {
+        vars.push(String::from(#tag));
+    });
+    let variant_vars = variants.iter().map(|(variant, ty)| {
+        let name = variant.to_string();
+        quote! {
+            vars.extend(<#ty as #krate::FromEnv>::env_vars(&#krate::__join_idents(ident, #name)));
+        }
+    });
+
+    let secret_tys = variants.iter().map(|(_, ty)| ty);
+    let loads = variants.iter().map(|(variant, ty)| {
+        let name = variant.to_string();
+        quote! {
+            Some(#name) => Ok(#s::#variant(<#ty as #krate::FromEnv>::load(&#krate::__join_idents(ident, #name))?))
+        }
+    });
+
+    quote! {
+        impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+            fn from_env(value: &str) -> Result<Self, #krate::EnvErrorType> {
+                Err(#krate::EnvErrorType::Other(String::from("'from' method not implemented for derive(FromEnv)")))
+            }
+
+            fn type_name() -> &'static str {
+                stringify!(#s)
+            }
+
+            fn is_secret() -> bool {
+                false #(|| <#secret_tys as #krate::FromEnv>::is_secret())*
+            }
+
+            fn load(ident: &str) -> Result<Self, #krate::EnvError> {
+                #select
+
+                match found {
+                    #(#loads,)*
+                    _ => Err(#krate::EnvError { var: String::from(ident), ty: #krate::EnvErrorType::NotPresent, expected: None, value: None })
+                }
+            }
+
+            fn env_vars(ident: &str) -> Vec<String> {
+                let mut vars = Vec::new();
+                #tag_var
+                #(#variant_vars)*
+                vars
+            }
+        }
+    }.into()
+}
+
+// Newtypes like `struct Port(u16)` parse from a single value, exactly like the type they wrap
+// With #[utils(separator = "...")] a newtype around a Vec<T> splits on that instead of commas,
+// e.g. `struct PathList(Vec<PathBuf>)` with ":"
+fn derive_newtype(s: &Ident, generics: &Generics, ty: &Type, container: &EnvContainer) -> TokenStream {
+    let krate = &container.krate;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let from_env = match &container.separator {
+        Some(separator) => {
+            let element = generic_inner(ty, "Vec").expect("'separator' on a newtype needs it to wrap a Vec");
+            quote! { #krate::__parse_list::<#element>(value, #separator, false) }
+        },
+        None => quote! { <#ty as #krate::FromEnv>::from_env(value) }
+    };
+
+    quote! {
+        impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+            fn from_env(value: &str) -> Result<Self, #krate::EnvErrorType> {
+                Ok(#s(#from_env?))
+            }
+
+            fn not_present() -> Result<Self, #krate::EnvErrorType> {
+                Ok(#s(<#ty as #krate::FromEnv>::not_present()?))
+            }
+
+            fn is_secret() -> bool {
+                <#ty as #krate::FromEnv>::is_secret()
+            }
+
+            fn type_name() -> &'static str {
+                stringify!(#s)
+            }
+        }
+    }.into()
+}
+
+// Unit structs read nothing, they always load
+fn derive_unit(s: &Ident, generics: &Generics, container: &EnvContainer) -> TokenStream {
+    let krate = &container.krate;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+            fn from_env(_value: &str) -> Result<Self, #krate::EnvErrorType> {
+                Ok(#s)
+            }
+
+            fn type_name() -> &'static str {
+                stringify!(#s)
+            }
+
+            fn load(_ident: &str) -> Result<Self, #krate::EnvError> {
+                Ok(#s)
+            }
+
+            fn env_vars(_ident: &str) -> Vec<String> {
+                Vec::new()
+            }
+
+            fn help_into(_ident: &str, _required: bool, _file: bool, _lines: &mut Vec<String>) {}
+        }
+    }.into()
+}
+
+#[proc_macro_derive(FromEnv, attributes(utils))]
+pub fn derive_env_config(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let container = handle_container(&ast.attrs);
+
+    derive_item(ast, container)
+}
+
+fn derive_item(ast: DeriveInput, container: EnvContainer) -> TokenStream {
+    let krate = &container.krate;
+    let s = ast.ident;
+
+    let generics = bounded_generics(&ast.generics, parse_quote!(#krate::FromEnv));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let data = match ast.data {
+        Data::Struct(ref data) => data,
+        Data::Enum(ref data) => { return derive_enum(&s, &generics, data, &container); },
+        _ => panic!("FromEnv can only be derived for structs and enums")
+    };
+
+    let named_fields = match data.fields {
+        Fields::Named(ref named_fields) => named_fields,
+        Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => { return derive_newtype(&s, &generics, &fields.unnamed[0].ty, &container); },
+        Fields::Unnamed(_) => panic!("Tuple structs must have exactly one field"),
+        Fields::Unit => { return derive_unit(&s, &generics, &container); }
+    };
+
+    // Fields behind a false #[cfg(...)] are already stripped by the compiler before the derive runs.
+    // #[utils(skip)] fields are left out of everything generated and start out as Default::default()
+    let (skipped, env_fields): (Vec<EnvField>, Vec<EnvField>) = named_fields.named.iter()
+        .map(|field| handle_field(field, &container.krate))
+        .partition(|field| field.skip);
+    let skipped: Vec<proc_macro2::TokenStream> = skipped.iter()
+        .map(|field| {
+            let ident = &field.ident;
+            quote! { #ident: Default::default() }
+        })
+        .collect();
+
+    if container.deny_unmarked {
+        if let Err(err) = check_marked(&env_fields) {
+            return err.to_compile_error().into();
+        }
+    }
+
+    if let Err(err) = check_default_secrets(&env_fields, &container) {
+        return err.to_compile_error().into();
+    }
+
+    if let Err(err) = check_bounds(&env_fields) {
+        return err.to_compile_error().into();
+    }
+
+    let order = match load_order(&env_fields) {
+        Ok(order) => order,
+        Err(err) => { return err.to_compile_error().into(); }
+    };
+
+    let bindings: Vec<proc_macro2::TokenStream> = order.iter()
+        .map(|index| env_fields[*index].binding_stmt(true))
+        .collect();
+
+    // Same as the bindings above, but errors are collected and the binding is an Option. Composed
+    // fields are skipped when a field they reference failed, that error is already recorded.
+    let all_bindings: Vec<proc_macro2::TokenStream> = order.iter()
+        .map(|index| {
+            let field = &env_fields[*index];
+            let ty = &field.ty;
+            let binding = field.binding();
+            let name = field.load_name_quote();
+            let loader = field.loader();
+            let consume = if field.consume {
+                let file = field.reads_file();
+                quote! { #krate::__consume(&name, #file); }
+            } else {
+                quote! {}
+            };
+
+            let load = if field.is_plain() {
+                quote! { <#ty as #krate::FromEnv>::load_all(&name).map(|value| { #consume value }) }
+            } else {
+                quote! { #loader.map(|value| { #consume value }).map_err(#krate::EnvErrors::from) }
+            };
+
+            let dependencies: Vec<Ident> = field.compose.iter().flat_map(|(_, fields)| fields).map(|field| format_ident!("__field_{}", field)).collect();
+            let load = if dependencies.is_empty() {
+                quote! { Some(#load) }
+            } else {
+                quote! {
+                    match (#(#dependencies.as_ref(),)*) {
+                        (#(Some(#dependencies),)*) => Some(#load),
+                        _ => None
+                    }
+                }
+            };
+
+            quote! {
+                let #binding = match #name {
+                    Ok(name) => match #load {
+                        Some(Ok(value)) => Some(value),
+                        Some(Err(#krate::EnvErrors(errs))) => { errors.extend(errs); None },
+                        None => None
+                    },
+                    Err(err) => { errors.push(err); None }
+                };
+            }
+        })
+        .collect();
+
+    let all_fields: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .map(|field| {
+            let ident = &field.ident;
+            let binding = field.binding();
+            quote! {
+                #ident: #binding.unwrap()
+            }
+        })
+        .chain(skipped.iter().cloned())
+        .collect();
+
+    let fields: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .map(|field| {
+            let ident = &field.ident;
+            let binding = field.binding();
+            quote! {
+                #ident: #binding
+            }
+        })
+        .chain(skipped.iter().cloned())
+        .collect();
+
+    let audits: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .map(|field| {
+            let ty = &field.ty;
+            let name = field.load_name_quote();
+            let loader = field.loader();
+            let file = field.reads_file();
+
+            let audit = if field.is_plain() {
+                quote! {
+                    <#ty as #krate::FromEnv>::audit_into(&name, report)
+                }
+            } else if field.compose.is_some() {
+                // Sibling fields aren't loaded while auditing, an unset composed field is just Unset
+                let raw = field.raw_file;
+                quote! {
+                    report.record(&name, #file, #krate::__load::<#ty>(&name, #file, #raw).map(|_| ()).or_else(|err| match err.ty {
+                        #krate::EnvErrorType::NotPresent => Ok(()),
+                        _ => Err(err)
+                    }))
+                }
+            } else {
+                quote! {
+                    report.record::<#ty>(&name, #file, #loader)
+                }
+            };
+
+            quote! {
+                match #name {
+                    Ok(name) => #audit,
+                    Err(err) => report.record::<#ty>(&err.var.clone(), false, Err(err))
+                }
+            }
+        })
+        .collect();
+
+    // One pair per var a field is loaded from, so nested structs list their own fields rather than themselves
+    let env_names: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .filter(|field| field.name_from.is_none() && field.index_from.is_none())
+        .map(|field| {
+            let ident = &field.ident;
+            let ty = &field.ty;
+            let name = field.name_quote();
+            let push = if field.is_plain() {
+                quote! { <#ty as #krate::FromEnv>::env_names_into(&name, &path, names); }
+            } else if field.file_only {
+                quote! { names.push((path, #krate::__file_ident(&name))); }
+            } else {
+                quote! { names.push((path, name)); }
+            };
+
+            quote! {
+                if let Ok(name) = #name {
+                    let path = #krate::__join_path(path, stringify!(#ident));
+                    #push
+                }
+            }
+        })
+        .collect();
+
+    // Names are listed without loading anything, bar the var a name_from or index_from field takes its prefix from
+    let env_vars: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .map(|field| {
+            let ty = &field.ty;
+            let name = field.name_quote();
+            let aliases = field.alias_quotes();
+            let file = field.reads_file();
+            let name_from = field.name_from.as_ref().or(field.index_from.as_ref()).map(|name_from| quote! {
+                vars.push(String::from(#name_from));
+            });
+
+            // A file_only field is only ever read through its _FILE var, so that's the one it lists
+            let vars = if field.is_plain() {
+                quote! { <#ty as #krate::FromEnv>::env_vars(&name) }
+            } else if field.file_only {
+                quote! { [#krate::__file_ident(&name)] }
+            } else {
+                quote! { #krate::__source_vars(&name, #file) }
+            };
+
+            quote! {
+                #name_from
+                if let Ok(name) = #name {
+                    for name in std::iter::once(name).chain([#(#aliases),*]) {
+                        vars.extend(#vars);
+                    }
+                }
+            }
+        })
+        .collect();
+
+    // Option fields and fields with a default are optional, so is everything under an optional nested struct
+    let helps: Vec<proc_macro2::TokenStream> = env_fields.iter()
+        .map(|field| {
+            let name = field.name_quote();
+            let file = field.reads_file();
+            let optional = option_inner(&field.ty).is_some() || field.has_default();
+            let required = quote! { required && !#optional };
+
+            let help = if field.is_plain() {
+                let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+                quote! { <#ty as #krate::FromEnv>::help_into(&name, #required, false, lines) }
+            } else if field.file_only {
+                quote! { lines.push(#krate::__help_line(&#krate::__file_ident(&name), #required, false)) }
+            } else {
+                quote! { lines.push(#krate::__help_line(&name, #required, #file)) }
+            };
+
+            quote! {
+                if let Ok(name) = #name {
+                    #help;
+                }
+            }
+        })
+        .collect();
+
+    let blob_var = container.blob_var.as_ref().map(|blob_var| quote! {
+        vars.push(String::from(#blob_var));
+    });
+
+    // #[utils(check_secrets)] flags fields named like secrets whose type isn't secret (e.g. not Masked),
+    // checked before anything is loaded. A secret struct masks everything, so there's nothing to flag.
+    let check_secrets: Vec<proc_macro2::TokenStream> = match container.check_secrets {
+        Some(deny) if !container.secret => {
+            let patterns = match &container.secret_patterns {
+                Some(patterns) => quote! { &[#(#patterns),*] },
+                None => quote! { #krate::SECRET_PATTERNS }
+            };
+
+            env_fields.iter()
+                .map(|field| {
+                    let ident = &field.ident;
+                    let ty = &field.ty;
+                    let name = field.name_quote();
+                    quote! {
+                        if let Ok(name) = #name {
+                            #krate::__check_secret(stringify!(#ident), &name, <#ty as #krate::FromEnv>::is_secret(), #patterns, #deny)?;
+                        }
+                    }
+                })
+                .collect()
+        },
+        _ => Vec::new()
+    };
+
+    let finalize = container.finalize.map(|finalize| quote! {
+        #finalize(&mut value).map_err(|ty| #krate::EnvError { var: #krate::__struct_var::<Self>(ident), ty, expected: None, value: None })?;
+    });
+
+    // Runs after finalize, so invariants are checked against the final values
+    let validate = container.validate.then(|| quote! {
+        #krate::Validate::validate(&value).map_err(|err| #krate::EnvError { var: #krate::__struct_var::<Self>(ident), ty: #krate::EnvErrorType::Other(err), expected: None, value: None })?;
+    });
+
+    // With #[utils(prefix = "...")] every generated fn puts the prefix in front of the ident it's given
+    let prefix = container.prefix.as_ref().map(|prefix| quote! {
+        let ident = &#krate::__prefix_ident(#prefix, ident);
+    });
+
+    // #[utils(separator = "...")] holds until the generated fn returns, so nested structs join with it too
+    let separator = container.separator.as_ref().map(|separator| quote! {
+        let _separator = #krate::__separator_scope(#separator);
+    });
+    let prefix = quote! {
+        #separator
+        #prefix
+    };
+
+    // Keyed by field name like to_json, nested structs return their own object
+    let schema_impl = cfg!(feature = "serde").then(|| {
+        let schemas = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            let name = field.name_quote();
+            let required = !(option_inner(&field.ty).is_some() || field.has_default());
+            let ty = option_inner(&field.ty).unwrap_or(&field.ty);
+            let var = if field.file_only {
+                quote! { let name = #krate::__file_ident(&name); }
+            } else {
+                quote! {}
+            };
+
+            let schema = if field.is_plain() {
+                quote! { <#ty as #krate::FromEnv>::schema_into(&name) }
+            } else if field.bypasses_from_env() {
+                quote! { #krate::__schema_var(&name, stringify!(#ty), false) }
+            } else {
+                quote! { #krate::__schema_var(&name, <#ty as #krate::FromEnv>::type_name(), <#ty as #krate::FromEnv>::is_secret()) }
+            };
+            let default = match &field.default {
+                Some(default) => quote! { Some(#default) },
+                None => quote! { None }
+            };
+            let description = match &field.description {
+                Some(description) => quote! { Some(#description) },
+                None => quote! { None }
+            };
+
+            quote! {
+                if let Ok(name) = #name {
+                    #var
+                    properties.insert(String::from(stringify!(#ident)), #krate::__schema_field(#schema, #default, #description));
+                    if #required {
+                        required.push(stringify!(#ident));
+                    }
+                }
+            }
+        });
+
+        quote! {
+            fn schema_into(ident: &str) -> #krate::serde_json::Value {
+                #prefix
+                let mut properties = #krate::serde_json::Map::new();
+                let mut required: Vec<&str> = Vec::new();
+                #(#schemas)*
+                #krate::__schema_object(properties, required)
+            }
+        }
+    });
+
+    let assemble = quote! {
+        {
+            #(#bindings)*
+
+            #s {
+                #(#fields),*
+            }
+        }
+    };
+
+    // The blob var wins when it's set, finalize and validate still run on the result
+    let assemble = match &container.blob_var {
+        Some(blob_var) => quote! {
+            match #krate::__load_blob::<Self>(#blob_var)? {
+                Some(value) => value,
+                None => #assemble
+            }
+        },
+        None => assemble
+    };
+
+    let assemble_all = quote! {
+        {
+            let mut errors: Vec<#krate::EnvError> = Vec::new();
+            #(#all_bindings)*
+
+            if !errors.is_empty() {
+                return Err(#krate::EnvErrors(errors));
+            }
+
+            #s {
+                #(#all_fields),*
+            }
+        }
+    };
+
+    let assemble_all = match &container.blob_var {
+        Some(blob_var) => quote! {
+            match #krate::__load_blob::<Self>(#blob_var)? {
+                Some(value) => value,
+                None => #assemble_all
+            }
+        },
+        None => assemble_all
+    };
+
+    let diff_impl = if container.diff {
+        let diffs = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            quote! {
+                #krate::Diff::diff_into(&self.#ident, &other.#ident, &#krate::__join_path(path, stringify!(#ident)), out);
+            }
+        });
+
+        let generics = bounded_generics(&ast.generics, parse_quote!(#krate::Diff));
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #krate::Diff for #s #ty_generics #where_clause {
+                fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+                    #(#diffs)*
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // #[utils(secret)] masks every value of the struct, nested ones included, and implies redact
+    let mask = container.secret.then(|| quote! {
+        values.values_mut().for_each(|value| *value = String::from("***"));
+    });
+
+    let redact_impl = if container.redact || container.secret {
+        let redacts = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            let name = field.name_quote();
+            quote! {
+                if let Ok(name) = #name {
+                    #krate::Redact::redact_into(&self.#ident, &name, &mut values);
+                }
+            }
+        });
+
+        let generics = bounded_generics(&ast.generics, parse_quote!(#krate::Redact));
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #krate::Redact for #s #ty_generics #where_clause {
+                fn redact_into(&self, ident: &str, out: &mut std::collections::HashMap<String, String>) {
+                    #prefix
+                    let mut values = std::collections::HashMap::new();
+                    #(#redacts)*
+                    #mask
+                    out.extend(values);
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Keyed by field name rather than var name, a secret struct has every field as "***"
+    let to_json_impl = if container.to_json {
+        let entries = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            let value = if container.secret {
+                quote! { #krate::serde_json::Value::from("***") }
+            } else {
+                quote! { #krate::ToJson::to_json(&self.#ident) }
+            };
+
+            quote! {
+                object.insert(String::from(stringify!(#ident)), #value);
+            }
+        });
+
+        let generics = bounded_generics(&ast.generics, parse_quote!(#krate::ToJson));
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #krate::ToJson for #s #ty_generics #where_clause {
+                fn to_json(&self) -> #krate::serde_json::Value {
+                    let mut object = #krate::serde_json::Map::new();
+                    #(#entries)*
+                    #krate::serde_json::Value::Object(object)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Takes every field directly, so tests can build a config without touching env.
+    // Into lets Masked fields be passed their plain value.
+    let builder_impl = if container.builder {
+        let params = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            let ty = &field.ty;
+            quote! { #ident: impl Into<#ty> }
+        });
+        let values = env_fields.iter().map(|field| {
+            let ident = &field.ident;
+            quote! { #ident: #ident.into() }
+        }).chain(skipped.iter().cloned());
+
+        let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #s #ty_generics #where_clause {
+                #[allow(clippy::too_many_arguments)]
+                pub fn from_values(#(#params),*) -> Self {
+                    #s {
+                        #(#values),*
+                    }
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // A process-wide singleton: init_global loads it once (e.g. in main) and global hands out the
+    // same &'static everywhere after. The struct has to be Send + Sync to live in the static.
+    let global_impl = if container.global {
+        if !ast.generics.params.is_empty() {
+            panic!("'global' can't be used on generic structs");
+        }
+
+        quote! {
+            impl #s {
+                fn __global_cell() -> &'static std::sync::OnceLock<#s> {
+                    static GLOBAL: std::sync::OnceLock<#s> = std::sync::OnceLock::new();
+                    &GLOBAL
+                }
+
+                // Loads on the first successful call, later calls return that config whatever their ident
+                pub fn init_global(ident: &str) -> Result<&'static Self, #krate::EnvError> {
+                    if let Some(value) = Self::__global_cell().get() {
+                        return Ok(value);
+                    }
+
+                    let value = <Self as #krate::FromEnv>::load(ident)?;
+                    Ok(Self::__global_cell().get_or_init(|| value))
+                }
+
+                // Panics if init_global hasn't succeeded yet
+                pub fn global() -> &'static Self {
+                    Self::__global_cell().get().expect(concat!(stringify!(#s), "::init_global has to succeed before global is used"))
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let env_names_impl = if container.env_names {
+        let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #krate::EnvNames for #s #ty_generics #where_clause {
+                fn env_names(ident: &str) -> Vec<(String, String)> {
+                    let mut names = Vec::new();
+                    <Self as #krate::FromEnv>::env_names_into(ident, "", &mut names);
+                    names
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // A field changed when one of its vars differs from the snapshot, or (for composed fields) one
+    // of the fields it's built from did. Plain fields can be nested structs, so their vars come
+    // from an audit rather than the name alone. Any change reloads the whole struct into a fresh
+    // value, so finalize and validate run as in load and a failed reload leaves self untouched.
+    let reload_impl = if container.reload {
+        let checks = order.iter().map(|index| {
+            let field = &env_fields[*index];
+            let ident = &field.ident;
+            let ty = &field.ty;
+            let name = field.load_name_quote();
+            let file = field.reads_file();
+            let dependencies = field.compose.iter().flat_map(|(_, fields)| fields);
+
+            let vars = if field.is_plain() {
+                quote! { <#ty as #krate::FromEnv>::audit(&name).entries.into_iter().map(|entry| entry.var).collect::<Vec<String>>() }
+            } else {
+                quote! { #krate::__source_vars(&name, #file) }
+            };
+
+            quote! {
+                let name = #name?;
+                if #krate::__changed(&#vars, &previous) #(|| changed.contains(&stringify!(#dependencies)))* {
+                    changed.push(stringify!(#ident));
+                }
+            }
+        });
+
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #s #ty_generics #where_clause {
+                // Take one right after loading, pass it to reload_changed on the next poll and
+                // then replace it with a fresh one, so each poll only compares against the
+                // environment the config was last loaded from. Reads the active source, if any.
+                pub fn snapshot() -> std::collections::HashMap<String, String> {
+                    #krate::__snapshot()
+                }
+
+                pub fn reload_changed(&mut self, snapshot: &std::collections::HashMap<String, String>, ident: &str) -> Result<Vec<&'static str>, #krate::EnvError> {
+                    let previous: std::sync::Arc<dyn #krate::EnvSource + Send + Sync> = std::sync::Arc::new(snapshot.clone());
+                    let mut changed: Vec<&'static str> = Vec::new();
+                    {
+                        #prefix
+                        #(#checks)*
+                    }
+
+                    if !changed.is_empty() {
+                        *self = <Self as #krate::FromEnv>::load(ident)?;
+                    }
+
+                    Ok(changed)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Dry run for `config --check` style tooling: parses every field like load (without consuming
+    // anything) and reports the redacted values, but never assembles the struct
+    let explain_impl = if container.explain {
+        let explain_bindings = order.iter().map(|index| env_fields[*index].binding_stmt(false));
+        let explains = order.iter().map(|index| {
+            let field = &env_fields[*index];
+            let binding = field.binding();
+            let name = field.name_quote();
+            quote! {
+                let mut values = std::collections::HashMap::new();
+                #krate::Redact::redact_into(&#binding, &#name?, &mut values);
+                #mask
+                let mut values: Vec<(String, String)> = values.into_iter().collect();
+                values.sort();
+                explained.extend(values);
+            }
+        });
+
+        let generics = bounded_generics(&ast.generics, parse_quote!(#krate::FromEnv));
+        let generics = bounded_generics(&generics, parse_quote!(#krate::Redact));
+        let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+        quote! {
+            impl #impl_generics #s #ty_generics #where_clause {
+                pub fn explain(ident: &str) -> Result<Vec<(String, String)>, #krate::EnvError> {
+                    #prefix
+                    #(#explain_bindings)*
+
+                    let mut explained = Vec::new();
+                    #(#explains)*
+                    Ok(explained)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let (blob_pair_sep, blob_entry_sep) = &container.blob_separators;
+
+    // Blob keys are field names, under rename_all also spelled the way the rule has them
+    let blob_renames = container.rename_all.iter().flat_map(|rule| env_fields.iter().map(move |field| {
+        let name = field.ident.to_string();
+        (rename_case(&name, rule), name)
+    }))
+        .filter(|(renamed, name)| renamed != name)
+        .map(|(renamed, name)| quote! { (#renamed, #name) });
+
+    let load = quote! {
+        #prefix
+        #(#check_secrets)*
+
+        #[allow(unused_mut)]
+        let mut value = #assemble;
+        #finalize
+        #validate
+        Ok(value)
+    };
+
+    let load_all = quote! {
+        #prefix
+        #(#check_secrets)*
+
+        #[allow(unused_mut)]
+        let mut value = #assemble_all;
+        #finalize
+        #validate
+        Ok(value)
+    };
+
+    // A secret container's Malformed entries drop their values, like its load errors do
+    let mask_audit = if container.secret {
+        quote! { #krate::__mask_audit(report, start); }
+    } else {
+        quote! {}
+    };
+
+    let audit = quote! {
+        #prefix
+        #[allow(unused_variables)]
+        let start = report.entries.len();
+        #(#audits;)*
+        #mask_audit
+    };
+
+    // With #[utils(case_insensitive)] the whole load runs under utils::__case_insensitive, nested structs included
+    let (load, load_all, audit) = if container.case_insensitive {
+        (quote! { #krate::__case_insensitive(|| { #load }) }, quote! { #krate::__case_insensitive(|| { #load_all }) }, quote! { #krate::__case_insensitive(|| { #audit }) })
+    } else {
+        (load, load_all, audit)
+    };
+
+    // A secret container keeps every value out of its errors, whatever the field types say
+    let (load, load_all) = if container.secret {
+        (
+            quote! { (|| -> Result<Self, #krate::EnvError> { #load })().map_err(#krate::__mask_error) },
+            quote! { (|| -> Result<Self, #krate::EnvErrors> { #load_all })().map_err(|errors| #krate::EnvErrors(errors.0.into_iter().map(#krate::__mask_error).collect())) }
+        )
+    } else {
+        (load, load_all)
+    };
+
+    // With #[utils(instances)], one config per instance set under `ident`, keyed by the instance
+    // name. With "upstream", `UPSTREAM_EU_HOST` and `UPSTREAM_US_HOST` give instances "EU" and "US":
+    // the instance is the first segment after the prefix, so instance names can't contain the
+    // separator themselves.
+    let instances_impl = if container.instances {
+        let load_instances = quote! { #krate::__load_instances::<Self>(ident) };
+        let load_instances = if container.case_insensitive {
+            quote! { #krate::__case_insensitive(|| #load_instances) }
+        } else {
+            load_instances
+        };
+
+        quote! {
+            impl #impl_generics #s #ty_generics #where_clause {
+                pub fn load_all_instances(ident: &str) -> Result<std::collections::HashMap<String, Self>, #krate::EnvError> {
+                    #load_instances
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Secret when the container is, or when any field is (e.g. a Masked one, loaded from a blob)
+    let secret = container.secret;
+    let secret_tys = env_fields.iter()
+        .filter(|field| !field.bypasses_from_env())
+        .map(|field| &field.ty);
+
+    quote! {
+        #diff_impl
+        #redact_impl
+        #to_json_impl
+        #builder_impl
+        #env_names_impl
+        #global_impl
+        #reload_impl
+        #explain_impl
+        #instances_impl
+
+        impl #impl_generics #krate::FromEnv for #s #ty_generics #where_clause {
+            fn from_env(value: &str) -> Result<Self, #krate::EnvErrorType> {
+                let ident = "";
+                #prefix
+                #krate::__from_blob(value, ident, #blob_pair_sep, #blob_entry_sep, &[#(#blob_renames),*])
+            }
+
+            fn type_name() -> &'static str {
+                stringify!(#s)
+            }
+
+            fn is_secret() -> bool {
+                #secret #(|| <#secret_tys as #krate::FromEnv>::is_secret())*
+            }
+
+            fn load(ident: &str) -> Result<Self, #krate::EnvError> {
+                #load
+            }
+
+            fn load_all(ident: &str) -> Result<Self, #krate::EnvErrors> {
+                #load_all
+            }
+
+            fn audit_into(ident: &str, report: &mut #krate::AuditReport) {
+                #audit
+            }
+
+            fn env_vars(ident: &str) -> Vec<String> {
+                #prefix
+                let mut vars = Vec::new();
+                #blob_var
+                #(#env_vars)*
+                vars
+            }
+
+            fn env_names_into(ident: &str, path: &str, names: &mut Vec<(String, String)>) {
+                #prefix
+                #(#env_names)*
+            }
+
+            fn help_into(ident: &str, required: bool, _file: bool, lines: &mut Vec<String>) {
+                #prefix
+                #(#helps)*
+            }
+
+            #schema_impl
         }
     }.into()
 }
\ No newline at end of file
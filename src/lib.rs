@@ -1,23 +1,126 @@
 use core::fmt;
-use std::{ffi::OsString, iter::once};
+use std::{collections::{BTreeSet, HashMap, HashSet}, ffi::OsString, hash::Hash, iter::once, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6}, num::{NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize}, path::PathBuf, sync::Arc, time::Duration};
+// Not every target has every width of atomic, so each is only there where the target has it
+#[cfg(target_has_atomic = "8")]
+use std::sync::atomic::{AtomicBool, AtomicI8, AtomicU8};
+#[cfg(target_has_atomic = "16")]
+use std::sync::atomic::{AtomicI16, AtomicU16};
+#[cfg(target_has_atomic = "32")]
+use std::sync::atomic::{AtomicI32, AtomicU32};
+#[cfg(target_has_atomic = "64")]
+use std::sync::atomic::{AtomicI64, AtomicU64};
+#[cfg(target_has_atomic = "ptr")]
+use std::sync::atomic::{AtomicIsize, AtomicUsize};
 
 extern crate self as utils;
 
+/// With `#[utils(deny_unmarked)]` every field has to be an `Option`, have a `default` or be
+/// marked `#[utils(required)]`, so nothing ends up required or optional by accident:
+///
+/// ```compile_fail
+/// use utils::FromEnv;
+///
+/// #[derive(FromEnv)]
+/// #[utils(deny_unmarked)]
+/// struct Config {
+///     #[utils(required)]
+///     host: String,
+///     #[utils(default = "8080")]
+///     port: u16,
+///     replicas: Option<u8>,
+///     // error: Field 'timeout' needs to be an Option, have a 'default' or be marked #[utils(required)]
+///     timeout: u64
+/// }
+/// ```
+///
+/// A secret field can't have a hardcoded `default` unless it's marked `#[utils(allow_default_secret)]`:
+///
+/// ```compile_fail
+/// use utils::{FromEnv, Masked};
+///
+/// #[derive(FromEnv)]
+/// struct Config {
+///     // error: Secret field 'password' can't have a 'default', mark it #[utils(allow_default_secret)] if that's intended
+///     #[utils(default = "hunter2")]
+///     password: Masked<String>
+/// }
+/// ```
+///
+/// At most one variant of a unit enum can catch the values no other variant matches:
+///
+/// ```compile_fail
+/// use utils::FromEnv;
+///
+/// #[derive(FromEnv)]
+/// enum Region {
+///     EuWest,
+///     #[utils(other)]
+///     Custom(String),
+///     // error: Only one variant can be marked #[utils(other)]
+///     #[utils(other)]
+///     Unknown(String)
+/// }
+/// ```
+///
+/// A min or max has to be a value of the field's number type:
+///
+/// ```compile_fail
+/// use utils::FromEnv;
+///
+/// #[derive(FromEnv)]
+/// struct Config {
+///     // error: 'max = 70000' on field 'port' isn't a valid u16
+///     #[utils(max = 70000)]
+///     port: u16
+/// }
+/// ```
 #[cfg(feature = "derive")]
 pub use utils_derive::*;
 
-#[derive(fmt::Debug)]
+// Generated ToJson impls build their objects through this
+#[cfg(feature = "serde")]
+pub use serde_json;
+
 pub enum EnvErrorType {
     NotPresent,
     NotUnicode(OsString),
     InvalidFormat,
+    // The value didn't parse as the expected type, with the reason the parser gave
+    Parse(String),
     Other(String)
 }
 
-#[derive(fmt::Debug)]
 pub struct EnvError {
     pub var: String,
-    pub ty: EnvErrorType
+    pub ty: EnvErrorType,
+    pub expected: Option<&'static str>,
+    // The value that failed to parse, left out for secret (Masked) types
+    pub value: Option<String>
+}
+
+thread_local! {
+    static VERBOSE_ERRORS: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Errors are terse by default, so values never end up in logs by accident. Call
+// `set_verbose_errors(true)` (e.g. in debug builds) to have Display include the offending value,
+// on the thread that renders the errors.
+pub fn set_verbose_errors(verbose: bool) {
+    VERBOSE_ERRORS.with(|flag| flag.set(verbose));
+}
+
+// The bytes of a NotUnicode value are only shown in verbose mode, like EnvError's value
+impl fmt::Debug for EnvErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnvErrorType::NotPresent => write!(f, "NotPresent"),
+            EnvErrorType::NotUnicode(value) if VERBOSE_ERRORS.with(|flag| flag.get()) => f.debug_tuple("NotUnicode").field(value).finish(),
+            EnvErrorType::NotUnicode(_) => write!(f, "NotUnicode(..)"),
+            EnvErrorType::InvalidFormat => write!(f, "InvalidFormat"),
+            EnvErrorType::Parse(reason) => f.debug_tuple("Parse").field(reason).finish(),
+            EnvErrorType::Other(err) => f.debug_tuple("Other").field(err).finish()
+        }
+    }
 }
 
 impl From<std::env::VarError> for EnvErrorType {
@@ -32,172 +135,6481 @@ impl From<std::env::VarError> for EnvErrorType {
 impl fmt::Display for EnvError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Error parsing environment variable '{}': ", self.var)?;
+        let verbose = VERBOSE_ERRORS.with(|flag| flag.get());
         match &self.ty {
             EnvErrorType::NotPresent => write!(f, "Not present"),
+            EnvErrorType::NotUnicode(value) if verbose && !value.is_empty() => write!(f, "Not valid unicode: '{}'", value.to_string_lossy()),
             EnvErrorType::NotUnicode(_) => write!(f, "Not valid unicode"),
-            EnvErrorType::InvalidFormat => write!(f, "Unable to parse"),
+            EnvErrorType::InvalidFormat => match self.expected {
+                Some(name) => write!(f, "Unable to parse as {}", name),
+                None => write!(f, "Unable to parse")
+            },
+            // e.g. "Unable to parse '3.x' as u16: invalid digit found in string", the value in verbose mode only
+            EnvErrorType::Parse(reason) => {
+                write!(f, "Unable to parse")?;
+                if let Some(value) = self.value.as_ref().filter(|_| verbose) {
+                    write!(f, " '{}'", value)?;
+                }
+
+                if let Some(name) = self.expected {
+                    write!(f, " as {}", name)?;
+                }
+
+                return write!(f, ": {}", reason);
+            },
             EnvErrorType::Other(err) => write!(f, "{}", err)
+        }?;
+
+        match &self.value {
+            Some(value) if verbose => write!(f, " (value '{}')", value),
+            _ => Ok(())
+        }
+    }
+}
+
+// Like Display, the value is only shown in verbose mode
+impl fmt::Debug for EnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("EnvError");
+        debug.field("var", &self.var).field("ty", &self.ty).field("expected", &self.expected);
+        if VERBOSE_ERRORS.with(|flag| flag.get()) {
+            debug.field("value", &self.value).finish()
+        } else {
+            debug.finish_non_exhaustive()
         }
     }
 }
 
 impl std::error::Error for EnvError {}
 
+// Errors from a secret container (or anything under it) drop the value they failed on, the bytes
+// of a NotUnicode one included
+pub fn __mask_error(mut err: EnvError) -> EnvError {
+    __mask_in_place(&mut err);
+    err
+}
+
+fn __mask_in_place(err: &mut EnvError) {
+    err.value = None;
+    if let EnvErrorType::NotUnicode(value) = &mut err.ty {
+        value.clear();
+    }
+}
+
+// Every error from a FromEnv::load_all, so a new deployment sees all of its missing vars at once
+#[derive(fmt::Debug)]
+pub struct EnvErrors(pub Vec<EnvError>);
+
+impl fmt::Display for EnvErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, err) in self.0.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "{}", err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for EnvErrors {}
+
+impl From<EnvError> for EnvErrors {
+    fn from(err: EnvError) -> Self {
+        EnvErrors(vec![err])
+    }
+}
+
 impl EnvError {
+    /// For custom loaders that read vars themselves:
+    ///
+    /// ```
+    /// use utils::{EnvError, EnvErrorType};
+    ///
+    /// let err = std::env::var("UTILS_DOC_UNSET").map_err(|err| EnvError::from_var_error("UTILS_DOC_UNSET", err)).unwrap_err();
+    /// assert!(matches!(err.ty, EnvErrorType::NotPresent));
+    ///
+    /// let err = std::fs::read_to_string("/nonexistent").map_err(|err| EnvError::from_io_error("UTILS_DOC_FILE", err)).unwrap_err();
+    /// assert_eq!(err.var, "UTILS_DOC_FILE");
+    /// ```
+    pub fn from_var_error(var: &str, err: std::env::VarError) -> EnvError {
+        EnvError { var: String::from(var), ty: err.into(), expected: None, value: None }
+    }
+
+    pub fn from_io_error(var: &str, err: std::io::Error) -> EnvError {
+        EnvError { var: String::from(var), ty: EnvErrorType::Other(err.to_string()), expected: None, value: None }
+    }
+
     fn convert<T, Err: Into<EnvErrorType>>(res: Result<T, Err>, ident: &str) -> Result<T, EnvError> {
-        res.map_err(|err| EnvError { var: String::from(ident), ty: err.into() })
+        res.map_err(|err| EnvError { var: String::from(ident), ty: err.into(), expected: None, value: None })
+    }
+
+    fn parse<T: FromEnv>(value: &str, ident: &str) -> Result<T, EnvError> {
+        T::from_env(value).map_err(|ty| EnvError { var: String::from(ident), ty, expected: Some(T::expected()), value: __error_value::<T>(value) })
     }
 }
 
 pub trait FromEnv where Self: Sized {
     fn from_env(value: &str) -> Result<Self, EnvErrorType>;
+
+    // Used when the variable is not set at all, Option<T> overrides this to return None
+    fn not_present() -> Result<Self, EnvErrorType> {
+        Err(EnvErrorType::NotPresent)
+    }
+
     fn load(ident: &str) -> Result<Self, EnvError> {
-        EnvError::convert(Self::from_env(&EnvError::convert(std::env::var(ident), ident)?), ident)
+        __load(ident, false, false)
     }
 
     fn load_or_file(ident: &str) -> Result<Self, EnvError> {
-        let str = match std::env::var(ident) {
-            Ok(value) => value,
-            Err(std::env::VarError::NotPresent) => {
-                let name = format!("{}_FILE", ident);
-                std::fs::read_to_string(EnvError::convert(std::env::var(&name), &name)?).map_err(|err| EnvError { var: name, ty: EnvErrorType::Other(err.to_string()) })?
+        __load(ident, true, false)
+    }
+
+    // Loads once to share between threads, the config is read-only from then on
+    fn load_shared(ident: &str) -> Result<Arc<Self>, EnvError> {
+        Self::load(ident).map(Arc::new)
+    }
+
+    // Like load, but every var is read from `source` rather than the process environment
+    fn load_from<S: EnvSource + Clone + Send + Sync + 'static>(ident: &str, source: &S) -> Result<Self, EnvError> {
+        __with_source(Arc::new(source.clone()), || Self::load(ident))
+    }
+
+    // Like load, but a var that isn't set under its exact name may match in a different case
+    fn load_ci(ident: &str) -> Result<Self, EnvError> {
+        __case_insensitive(|| Self::load(ident))
+    }
+
+    // Loads only when the `gate` var is true, an unset or false gate skips loading (and validation) entirely
+    fn load_if_enabled(gate: &str, ident: &str) -> Result<Option<Self>, EnvError> {
+        if Option::<bool>::load(gate)?.unwrap_or(false) {
+            Self::load(ident).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Fills in vars from a `.env` style file, real environment vars (or those of the active source)
+    // win over the file. Unlike dotenv the process environment is left as it is.
+    fn load_dotenv(path: &str, ident: &str) -> Result<Self, EnvError> {
+        let vars = __dotenv_vars(path)?;
+        __with_source(Arc::new(__over_active(vars)), || Self::load(ident))
+    }
+
+    // Like load, but derived structs keep going after a bad field and report every error, nested ones included
+    fn load_all(ident: &str) -> Result<Self, EnvErrors> {
+        Ok(Self::load(ident)?)
+    }
+
+    // Checks every var this type would read without short-circuiting, derived structs recurse into their fields
+    fn audit(ident: &str) -> AuditReport {
+        let mut report = AuditReport::default();
+        Self::audit_into(ident, &mut report);
+        report
+    }
+
+    fn audit_into(ident: &str, report: &mut AuditReport) {
+        report.record(ident, false, Self::load(ident));
+    }
+
+    // Every var a load would consult, in declaration order, without loading anything. Derived structs
+    // recurse into nested fields and include the `_FILE` and alias vars of fields that read them.
+    fn env_vars(ident: &str) -> Vec<String> {
+        vec![String::from(ident)]
+    }
+
+    // The (field path, var) pairs behind EnvNames, e.g. ("database.url", "APP_DATABASE_URL").
+    // Derived structs recurse into nested fields like env_vars, everything else is the field itself.
+    fn env_names_into(ident: &str, path: &str, names: &mut Vec<(String, String)>) {
+        names.extend(Self::env_vars(ident).into_iter().map(|var| (String::from(path), var)));
+    }
+
+    // One line per var a load reads, e.g. `APP_ID (required)` or `TEST_NAME (required, or TEST_NAME_FILE)`,
+    // in declaration order. Derived structs recurse into nested fields.
+    fn help(ident: &str) -> String {
+        let mut lines = Vec::new();
+        Self::help_into(ident, true, false, &mut lines);
+        lines.join("\n")
+    }
+
+    fn help_into(ident: &str, required: bool, file: bool, lines: &mut Vec<String>) {
+        lines.push(__help_line(ident, required, file));
+    }
+
+    // Machine-readable form of help as a JSON Schema (draft 2020-12), e.g. `{"type": "integer",
+    // "x-env-var": "APP_ID"}`. Derived structs give an object with a property per field, along with
+    // its default and doc comment, and list the fields without either in `required`. The var is under
+    // the non-standard `x-env-var` keyword and secrets are marked `writeOnly`.
+    #[cfg(feature = "serde")]
+    fn json_schema(ident: &str) -> serde_json::Value {
+        let mut schema = Self::schema_into(ident);
+        if let serde_json::Value::Object(object) = &mut schema {
+            object.insert(String::from("$schema"), serde_json::Value::from("https://json-schema.org/draft/2020-12/schema"));
+        }
+
+        schema
+    }
+
+    #[cfg(feature = "serde")]
+    fn schema_into(ident: &str) -> serde_json::Value {
+        __schema_var(ident, Self::type_name(), Self::is_secret())
+    }
+
+    // Secret types keep their value out of errors, Masked<T> overrides this
+    fn is_secret() -> bool {
+        false
+    }
+
+    // Name of the expected type, used in "Unable to parse as ..." errors
+    fn type_name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    // What a value that fails to parse is reported against (EnvError::expected), the type name
+    // unless the type can say more, e.g. the names a derived unit enum accepts
+    fn expected() -> &'static str {
+        Self::type_name()
+    }
+}
+
+#[derive(fmt::Debug)]
+pub enum AuditStatus {
+    Present,
+    // Not set, but the value is optional
+    Unset,
+    Missing,
+    Malformed(EnvError)
+}
+
+#[derive(fmt::Debug)]
+pub struct AuditEntry {
+    pub var: String,
+    pub status: AuditStatus
+}
+
+// Pre-flight report from FromEnv::audit, parsed values are discarded so secrets never end up in it
+#[derive(fmt::Debug, Default)]
+pub struct AuditReport {
+    pub entries: Vec<AuditEntry>
+}
+
+impl AuditReport {
+    pub fn is_ok(&self) -> bool {
+        self.entries.iter().all(|entry| matches!(entry.status, AuditStatus::Present | AuditStatus::Unset))
+    }
+
+    pub fn record<T>(&mut self, ident: &str, file: bool, res: Result<T, EnvError>) {
+        let (var, status) = match res {
+            Ok(_) => {
+                let present = __is_set(ident) || (file && __is_set(&__file_ident(ident)));
+                (String::from(ident), if present { AuditStatus::Present } else { AuditStatus::Unset })
             },
-            Err(err) => { return Err(EnvError { var: String::from(ident), ty: err.into() }); }
+            Err(EnvError { var, ty: EnvErrorType::NotPresent, .. }) => (var, AuditStatus::Missing),
+            Err(err) => (err.var.clone(), AuditStatus::Malformed(err))
         };
 
-        EnvError::convert(Self::from_env(&str), ident)
+        self.entries.push(AuditEntry { var, status });
     }
 }
 
-macro_rules! impl_from_env {
-    ($($t:ty),*) => {
-        $(impl FromEnv for $t {
-            fn from_env(value: &str) -> Result<Self, EnvErrorType> {
-                value.parse().map_err(|_| EnvErrorType::InvalidFormat)
-            }
-        })*
-    };
+// Masks the errors of the entries recorded from `start` on, for secret containers
+pub fn __mask_audit(report: &mut AuditReport, start: usize) {
+    for entry in &mut report.entries[start..] {
+        if let AuditStatus::Malformed(err) = &mut entry.status {
+            __mask_in_place(err);
+        }
+    }
 }
 
-impl_from_env!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
-impl_from_env!(f32, f64, bool, String);
+// Backs #[utils(default = "...")]: only a missing var falls back, the default is parsed like a
+// real value would be, and anything else (an invalid value, an unreadable _FILE) still errors
+pub fn __or_default<T: FromEnv>(ident: &str, res: Result<T, EnvError>, default: &str) -> Result<T, EnvError> {
+    match res {
+        Err(EnvError { ty: EnvErrorType::NotPresent, var, .. }) if var == ident => EnvError::parse(default, ident),
+        res => res
+    }
+}
 
-impl<T> FromEnv for Option<T> where T: FromEnv {
-    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
-        Ok(Some(T::from_env(value)?))
+// Backs #[utils(default_expr = ...)], like __or_default but the fallback is already a T
+pub fn __or_else<T>(ident: &str, res: Result<T, EnvError>, default: impl FnOnce() -> T) -> Result<T, EnvError> {
+    match res {
+        Err(EnvError { ty: EnvErrorType::NotPresent, var, .. }) if var == ident => Ok(default()),
+        res => res
     }
+}
 
-    fn load(ident: &str) -> Result<Self, EnvError> {
-        match std::env::var(ident) {
-            Ok(value) => EnvError::convert(FromEnv::from_env(&value), ident),
-            Err(std::env::VarError::NotPresent) => Ok(None),
-            Err(err) => Err(EnvError { var: String::from(ident), ty: err.into() })
+// Backs #[utils(compose = "...")]: the var wins when set, otherwise the value is built from
+// the template over already loaded sibling fields
+pub fn __load_or<T: FromEnv>(ident: &str, file: bool, raw: bool, fallback: impl FnOnce() -> String) -> Result<T, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) => EnvError::parse(&value, ident),
+        None => EnvError::parse(&fallback(), ident)
+    }
+}
+
+// Every var that is set, in the source if there is one. Backs the snapshot method generated with
+// #[utils(reload)] and EnvSnapshot::capture.
+pub fn __snapshot() -> HashMap<String, String> {
+    match SOURCE.with(|source| source.borrow().clone()) {
+        Some(source) => source.keys().into_iter().filter_map(|key| Some((key.clone(), source.get(&key).ok()?))).collect(),
+        None => std::env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .collect()
+    }
+}
+
+pub fn __source_vars(ident: &str, file: bool) -> Vec<String> {
+    let mut vars = vec![String::from(ident)];
+    if file {
+        vars.push(__file_ident(ident));
+    }
+
+    vars
+}
+
+pub fn __help_line(ident: &str, required: bool, file: bool) -> String {
+    let required = if required { "required" } else { "optional" };
+    if file {
+        format!("{} ({}, or {})", ident, required, __file_ident(ident))
+    } else {
+        format!("{} ({})", ident, required)
+    }
+}
+
+// The JSON type a value of the type is written as, by its type_name. Anything that isn't a
+// number or a bool (lists and maps included) is given as a string in the environment.
+#[cfg(feature = "serde")]
+fn __json_type(ty: &str) -> &'static str {
+    let ty = ty.strip_prefix("core::num::nonzero::NonZero<").and_then(|ty| ty.strip_suffix('>')).unwrap_or(ty);
+    match ty {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => "integer",
+        "f32" | "f64" => "number",
+        "bool" => "boolean",
+        _ => "string"
+    }
+}
+
+#[cfg(feature = "serde")]
+pub fn __schema_var(ident: &str, ty: &str, secret: bool) -> serde_json::Value {
+    let mut schema = serde_json::json!({ "type": __json_type(ty), "x-env-var": ident });
+    if secret {
+        schema["writeOnly"] = serde_json::Value::Bool(true);
+    }
+
+    schema
+}
+
+#[cfg(feature = "serde")]
+pub fn __schema_object(properties: serde_json::Map<String, serde_json::Value>, required: Vec<&str>) -> serde_json::Value {
+    serde_json::json!({ "type": "object", "properties": properties, "required": required })
+}
+
+// Adds what only the derive knows about a field to the schema its type gave. Defaults of numbers
+// and bools are given as such, so `default = "5432"` is 5432 on an integer.
+#[cfg(feature = "serde")]
+pub fn __schema_field(mut schema: serde_json::Value, default: Option<&str>, description: Option<&str>) -> serde_json::Value {
+    if let serde_json::Value::Object(object) = &mut schema {
+        if let Some(default) = default {
+            let default = match object.get("type").and_then(serde_json::Value::as_str) {
+                Some("integer" | "number" | "boolean") => serde_json::from_str(default).unwrap_or_else(|_| serde_json::Value::from(default)),
+                _ => serde_json::Value::from(default)
+            };
+            object.insert(String::from("default"), default);
+        }
+        if let Some(description) = description {
+            object.insert(String::from("description"), serde_json::Value::from(description));
         }
     }
 
-    fn load_or_file(ident: &str) -> Result<Self, EnvError> {
-        let str = match std::env::var(ident) {
-            Ok(value) => value,
-            Err(std::env::VarError::NotPresent) => match std::env::var(format!("{}_FILE", ident)) {
-                Ok(path) => std::fs::read_to_string(path).map_err(|err| EnvError { var: String::from(ident), ty: EnvErrorType::Other(err.to_string()) })?,
-                Err(std::env::VarError::NotPresent) => { return Ok(None); },
-                Err(err) => { return Err(EnvError { var: String::from(ident), ty: err.into() }); }
-            },
-            Err(err) => { return Err(EnvError { var: String::from(ident), ty: err.into() }); }
-        };
+    schema
+}
+
+// Backs #[utils(alias = "...")]: the first of the names any of whose vars is set, falling back to
+// the primary name so a missing field still reports that one. `vars` lists the vars of a name,
+// e.g. the var and its `_FILE` var, or every var of a nested struct under it.
+pub fn __first_set(primary: String, aliases: &[String], vars: impl Fn(&str) -> Vec<String>) -> String {
+    once(&primary).chain(aliases)
+        .find(|name| vars(name).iter().any(|var| __is_set(var)))
+        .cloned()
+        .unwrap_or(primary)
+}
+
+// The snapshot is read with the same lookup as the live vars (case-insensitive matches, blank as
+// unset), so only a real change to a value counts
+pub fn __changed(vars: &[String], snapshot: &Arc<dyn EnvSource + Send + Sync>) -> bool {
+    vars.iter().any(|var| __var(var).ok() != __with_source(snapshot.clone(), || __var(var).ok()))
+}
+
+// Where vars are looked up, FromEnv::load_from reads from one instead of the process environment.
+// A HashMap makes an isolated environment for tests, StdEnv is the process environment itself.
+pub trait EnvSource {
+    fn get(&self, key: &str) -> Result<String, std::env::VarError>;
 
-        EnvError::convert(FromEnv::from_env(&str), ident)
+    // Every var name, for case-insensitive lookups. Sources that can't list theirs only match exactly.
+    fn keys(&self) -> Vec<String> {
+        Vec::new()
     }
+
+    // Backs #[utils(consume)]. Sources that can't forget a var (like a HashMap) keep it.
+    fn forget(&self, _key: &str) {}
 }
 
-pub struct Masked<T>(pub T);
+#[derive(fmt::Debug, Clone, Copy, Default)]
+pub struct StdEnv;
 
-impl<T> FromEnv for Masked<T> where T: FromEnv {
-    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
-        T::from_env(value).map(|val| Masked(val))
+impl EnvSource for StdEnv {
+    fn get(&self, key: &str) -> Result<String, std::env::VarError> {
+        std::env::var(key)
+    }
+
+    fn forget(&self, key: &str) {
+        std::env::remove_var(key);
+    }
+
+    fn keys(&self) -> Vec<String> {
+        std::env::vars_os().filter_map(|(key, _)| key.into_string().ok()).collect()
     }
 }
 
-impl<T> From<T> for Masked<T> {
-    fn from(value: T) -> Self {
-        Masked(value)
+impl EnvSource for HashMap<String, String> {
+    fn get(&self, key: &str) -> Result<String, std::env::VarError> {
+        HashMap::get(self, key).cloned().ok_or(std::env::VarError::NotPresent)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        HashMap::keys(self).cloned().collect()
     }
 }
 
-impl<T> fmt::Debug for Masked<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "***")
+// Several sources in order of precedence, the first one a var is set in wins. The crate doesn't
+// talk to config stores itself, a store like etcd or consul plugs in by implementing EnvSource
+// over a client (or a cache it keeps up to date) and going after the environment:
+//
+//     let source = ChainedSource::new().then(StdEnv).then(ConsulSource::new(client));
+//     let config = Config::load_from("app", &source)?;
+#[derive(Clone, Default)]
+pub struct ChainedSource {
+    sources: Vec<Arc<dyn EnvSource + Send + Sync>>
+}
+
+impl ChainedSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a source below the ones already in the chain
+    pub fn then<S: EnvSource + Send + Sync + 'static>(mut self, source: S) -> Self {
+        self.sources.push(Arc::new(source));
+        self
     }
 }
 
-impl<T> fmt::Display for Masked<T> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "***")
+impl EnvSource for ChainedSource {
+    fn get(&self, key: &str) -> Result<String, std::env::VarError> {
+        self.sources.iter()
+            .map(|source| source.get(key))
+            .find(|res| !matches!(res, Err(std::env::VarError::NotPresent)))
+            .unwrap_or(Err(std::env::VarError::NotPresent))
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let keys: BTreeSet<String> = self.sources.iter().flat_map(|source| source.keys()).collect();
+        keys.into_iter().collect()
+    }
+
+    fn forget(&self, key: &str) {
+        for source in &self.sources {
+            source.forget(key);
+        }
     }
 }
 
-fn __convert_ident(ident: impl Iterator<Item = char>) -> impl Iterator<Item = char> {
-    ident.flat_map(|ch| ch.to_uppercase())
+// The active source (or the process environment) over `below`, which only fills in what it doesn't set
+fn __over_active<S: EnvSource + Send + Sync + 'static>(below: S) -> ChainedSource {
+    ChainedSource { sources: vec![__active_source(), Arc::new(below)] }
 }
 
-pub fn __join_idents(ident: &str, postfix: &str) -> String {
-    if ident.is_empty() {
-        __convert_ident(postfix.chars())
-            .collect()
-    } else {
-        __convert_ident(ident.chars())
-            .chain(once('_'))
-            .chain(__convert_ident(postfix.chars()))
-            .collect()
+fn __active_source() -> Arc<dyn EnvSource + Send + Sync> {
+    SOURCE.with(|source| source.borrow().clone()).unwrap_or_else(|| Arc::new(StdEnv))
+}
+
+// Keys are joined like field names, so `{"db": {"port": 5432}}` under "app" is APP_DB_PORT. Lists of
+// plain values become comma separated lists, anything else nested in a list stays JSON.
+#[cfg(feature = "serde")]
+fn __flatten_json(value: &serde_json::Value, ident: &str, vars: &mut HashMap<String, String>) {
+    let plain = |value: &serde_json::Value| match value {
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string()
+    };
+
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                __flatten_json(value, &__join_idents(ident, key), vars);
+            }
+        },
+        serde_json::Value::Null => {},
+        serde_json::Value::Array(values) if values.iter().all(|value| !(value.is_array() || value.is_object())) => {
+            vars.insert(String::from(ident), values.iter().map(plain).collect::<Vec<String>>().join(","));
+        },
+        value => { vars.insert(String::from(ident), plain(value)); }
     }
 }
 
-#[cfg(test)]
-mod test {
-    #[cfg(feature = "derive")]
-    #[derive(FromEnv, Debug)]
-    struct NestedConfig {
-        host: String,
-        address: Option<Masked<String>>
+// Loads from a JSON config file with the environment (or the active source) on top: a set var wins
+// over the file and the file wins over #[utils(default)]s. A file that doesn't exist is the same
+// as an empty one. Only JSON is read, other formats like TOML have to be converted first.
+#[cfg(feature = "serde")]
+pub fn load_layered<T: FromEnv>(path: impl AsRef<std::path::Path>, ident: &str) -> Result<T, EnvError> {
+    let path = path.as_ref();
+    let name = path.to_string_lossy();
+    let mut file = HashMap::new();
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            let value: serde_json::Value = serde_json::from_str(&contents).map_err(|err| EnvError { var: name.to_string(), ty: EnvErrorType::Other(err.to_string()), expected: None, value: None })?;
+            if !value.is_object() {
+                return Err(EnvError { var: name.to_string(), ty: EnvErrorType::Other(String::from("Expected a JSON object")), expected: None, value: None });
+            }
+
+            __flatten_json(&value, ident, &mut file);
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+        Err(err) => { return Err(EnvError::from_io_error(&name, err)); }
     }
-    
-    #[cfg(feature = "derive")]
-    #[derive(FromEnv, Debug)]
-    struct TestConfig {
-        id: i32,
-        #[utils(var_or_file, name = "TEST_NAME")]
-        name: String,
-        guest_id: Option<u64>,
-        nested: NestedConfig
+
+    __with_source(Arc::new(__over_active(file)), || T::load(ident))
+}
+
+// load_layered for desktop and CLI apps: `{config dir}/{app_name}/config.json` (e.g.
+// ~/.config/app/config.json on Linux) under vars prefixed with the app name. Without a config dir
+// (e.g. no HOME) there's no file to read, so only the vars are.
+#[cfg(all(feature = "serde", feature = "dirs"))]
+pub fn load_layered_config<T: FromEnv>(app_name: &str) -> Result<T, EnvError> {
+    match dirs::config_dir() {
+        Some(dir) => load_layered(dir.join(app_name).join("config.json"), app_name),
+        None => T::load(app_name)
     }
+}
 
-    #[cfg(feature = "derive")]
-    #[test]
-    fn test_regular() {
-        std::env::set_var("ID", "5");
-        std::env::remove_var("TEST_NAME_FILE");
-        std::env::set_var("TEST_NAME", "john doe");
-        std::env::set_var("GUEST_ID", "4");
-        std::env::set_var("NESTED_HOST", "yoyo");
+// Runs the load with every var read from `source`, nested structs included
+fn __with_source<T>(source: Arc<dyn EnvSource + Send + Sync>, load: impl FnOnce() -> T) -> T {
+    let _scope = SourceScope(SOURCE.with(|current| current.replace(Some(source))));
+    load()
+}
 
-        let config = TestConfig::load("").expect("Config should parse correctly");
-        assert_eq!(config.id, 5);
-        assert_eq!(config.name, "john doe");
-        assert_eq!(config.guest_id, Some(4));
-        assert_eq!(config.nested.host, "yoyo");
-        assert!(config.nested.address.is_none());
+// Restores the source that was in use before __with_source, also when the load panics
+struct SourceScope(Option<Arc<dyn EnvSource + Send + Sync>>);
+
+impl Drop for SourceScope {
+    fn drop(&mut self) {
+        SOURCE.with(|current| current.replace(self.0.take()));
     }
+}
 
-    #[cfg(feature = "derive")]
-    #[test]
-    fn test_file() {
-        std::env::set_var("ID", "5");
-        std::env::set_var("GUEST_ID", "4");
-        std::env::set_var("NESTED_HOST", "yoyo");
-        std::env::remove_var("TEST_NAME");
-        std::env::set_var("TEST_NAME_FILE", "test_name.txt");
+// A copy of the environment taken once, so several configs can be loaded from the same scan and
+// are guaranteed to see the same values. Loading through it reads only the copy, never the live env.
+#[derive(fmt::Debug, Clone)]
+pub struct EnvSnapshot {
+    vars: Arc<HashMap<String, String>>
+}
 
-        let config2 = TestConfig::load("").expect("Config should parse correctly");
-        assert_eq!(config2.name, "test");
+thread_local! {
+    static SOURCE: std::cell::RefCell<Option<Arc<dyn EnvSource + Send + Sync>>> = const { std::cell::RefCell::new(None) };
+    static CASE_INSENSITIVE: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    static BLANK_AS_UNSET: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    static SEPARATOR: std::cell::Cell<&'static str> = const { std::cell::Cell::new("_") };
+}
+
+impl EnvSnapshot {
+    pub fn capture() -> Self {
+        EnvSnapshot::from(__snapshot())
+    }
+
+    pub fn load<T: FromEnv>(&self, ident: &str) -> Result<T, EnvError> {
+        __with_source(self.vars.clone(), || T::load(ident))
+    }
+}
+
+impl From<HashMap<String, String>> for EnvSnapshot {
+    fn from(vars: HashMap<String, String>) -> Self {
+        EnvSnapshot { vars: Arc::new(vars) }
+    }
+}
+
+pub fn __is_set(ident: &str) -> bool {
+    !matches!(__var(ident), Err(std::env::VarError::NotPresent))
+}
+
+// Windows treats var names case-insensitively, so a var the exact lookup misses may still be set
+// as e.g. `Path` rather than `PATH`. Other platforms only match the exact name, unless the load
+// runs under __case_insensitive. An exact match always wins, then the first var that matches.
+fn __var(ident: &str) -> Result<String, std::env::VarError> {
+    match __var_exact(ident) {
+        Ok(value) if value.trim().is_empty() && BLANK_AS_UNSET.with(|flag| flag.get()) => Err(std::env::VarError::NotPresent),
+        res => res
+    }
+}
+
+fn __var_exact(ident: &str) -> Result<String, std::env::VarError> {
+    let source = SOURCE.with(|source| source.borrow().clone());
+    let res = match &source {
+        Some(source) => source.get(ident),
+        None => std::env::var(ident)
+    };
+
+    if matches!(res, Err(std::env::VarError::NotPresent)) && (cfg!(windows) || CASE_INSENSITIVE.with(|flag| flag.get())) {
+        let found = match &source {
+            Some(source) => source.keys().into_iter().find(|key| key.eq_ignore_ascii_case(ident)).map(|key| source.get(&key)),
+            None => std::env::vars_os()
+                .find(|(key, _)| key.to_str().is_some_and(|key| key.eq_ignore_ascii_case(ident)))
+                .map(|(_, value)| value.into_string().map_err(std::env::VarError::NotUnicode))
+        };
+
+        if let Some(found) = found {
+            return found;
+        }
+    }
+
+    res
+}
+
+// Names of the vars that are set, in the source if there is one
+fn __var_names() -> Vec<String> {
+    match SOURCE.with(|source| source.borrow().clone()) {
+        Some(source) => source.keys(),
+        None => std::env::vars_os().filter_map(|(key, _)| key.into_string().ok()).collect()
+    }
+}
+
+// The distinct first segments of vars under `ident`, bar the ones with nothing after them. Under
+// a case-insensitive load the prefix matches in any case and names differing only in case are
+// one instance, spelled as the first var that has it.
+fn __instance_names(ident: &str) -> BTreeSet<String> {
+    let prefix = __join_idents(ident, "");
+    let separator = SEPARATOR.with(|separator| separator.get());
+    let case_insensitive = cfg!(windows) || CASE_INSENSITIVE.with(|flag| flag.get());
+
+    let mut names = BTreeSet::new();
+    for var in __var_names() {
+        let rest = match var.get(..prefix.len()) {
+            Some(head) if head == prefix || (case_insensitive && head.eq_ignore_ascii_case(&prefix)) => &var[prefix.len()..],
+            _ => continue
+        };
+
+        let Some((name, rest)) = rest.split_once(separator) else { continue };
+        if name.is_empty() || rest.is_empty() || (case_insensitive && names.iter().any(|known: &String| known.eq_ignore_ascii_case(name))) {
+            continue;
+        }
+
+        names.insert(String::from(name));
+    }
+
+    names
+}
+
+// Backs the load_all_instances generated by #[utils(instances)]. Only names with at least one of
+// T's own vars set are instances, so an unrelated `UPSTREAM_DEBUG_LEVEL` isn't an instance "DEBUG".
+pub fn __load_instances<T: FromEnv>(ident: &str) -> Result<HashMap<String, T>, EnvError> {
+    __instance_names(ident).into_iter()
+        .filter(|name| T::env_vars(&__join_idents(ident, name)).iter().any(|var| __is_set(var)))
+        .map(|name| T::load(&__join_idents(ident, &name)).map(|config| (name, config)))
+        .collect()
+}
+
+// Backs FromEnv::load_ci and #[utils(case_insensitive)], every var read by `load` falls back to
+// a case-insensitive match when the exact name isn't set
+pub fn __case_insensitive<T>(load: impl FnOnce() -> T) -> T {
+    let _scope = FlagScope::set(&CASE_INSENSITIVE);
+    load()
+}
+
+// Sets a read flag until dropped, then restores what it was before, also when the load panics
+struct FlagScope(&'static std::thread::LocalKey<std::cell::Cell<bool>>, bool);
+
+impl FlagScope {
+    fn set(flag: &'static std::thread::LocalKey<std::cell::Cell<bool>>) -> Self {
+        FlagScope(flag, flag.with(|flag| flag.replace(true)))
+    }
+}
+
+impl Drop for FlagScope {
+    fn drop(&mut self) {
+        self.0.with(|flag| flag.set(self.1));
+    }
+}
+
+// Backs #[utils(blank_as_unset)], for templates that render an unfilled placeholder as spaces:
+// an empty or whitespace-only var reads as unset for the duration of `load`
+pub fn __blank_as_unset<T>(load: impl FnOnce() -> T) -> T {
+    let _scope = FlagScope::set(&BLANK_AS_UNSET);
+    load()
+}
+
+// Sets the vars from a `KEY=VALUE` file that aren't already set in the process environment
+pub fn dotenv(path: &str) -> Result<(), EnvError> {
+    for (key, value) in __dotenv_vars(path)? {
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+// The vars of a `KEY=VALUE` file. Blank lines and `#` comments are skipped and a value wrapped in
+// matching single or double quotes has them removed.
+fn __dotenv_vars(path: &str) -> Result<HashMap<String, String>, EnvError> {
+    let mut vars = HashMap::new();
+    let contents = std::fs::read_to_string(path).map_err(|err| EnvError::from_io_error(path, err))?;
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| EnvError { var: String::from(path), ty: EnvErrorType::Other(format!("Invalid line {}, expected 'KEY=VALUE'", index + 1)), expected: None, value: None })?;
+        let (key, value) = (key.trim(), value.trim());
+        let value = ['"', '\''].iter()
+            .find_map(|quote| value.strip_prefix(*quote).and_then(|value| value.strip_suffix(*quote)))
+            .unwrap_or(value);
+
+        vars.entry(String::from(key)).or_insert_with(|| String::from(value));
+    }
+
+    Ok(vars)
+}
+
+// Reads the raw value, or None if neither the var nor (with `file`) its _FILE companion is set.
+// File contents keep everything except a single trailing newline (so PEM blocks and
+// other multi-line secrets stay intact), unless `raw` asks for the file byte-for-byte.
+pub fn __read(ident: &str, file: bool, raw: bool) -> Result<Option<String>, EnvError> {
+    match __var(ident) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) if file => {
+            let name = __file_ident(ident);
+            let path = match __var(&name) {
+                Ok(path) => path,
+                Err(std::env::VarError::NotPresent) => { return Ok(None); },
+                Err(err) => { return EnvError::convert(Err(err), &name); }
+            };
+
+            let mut contents = __read_file(&path).map_err(|err| EnvError::from_io_error(&name, err))?;
+            if !raw {
+                if contents.ends_with("\r\n") {
+                    contents.truncate(contents.len() - 2);
+                } else if contents.ends_with('\n') {
+                    contents.pop();
+                }
+            }
+
+            Ok(Some(contents))
+        },
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => EnvError::convert(Err(err), ident)
+    }
+}
+
+// The _FILE companion follows the casing of the var, so `token` pairs with `token_file`
+pub fn __file_ident(ident: &str) -> String {
+    let lowercase = ident.chars().any(char::is_lowercase) && !ident.chars().any(char::is_uppercase);
+    format!("{}{}", ident, if lowercase { "_file" } else { "_FILE" })
+}
+
+pub fn __load<T: FromEnv>(ident: &str, file: bool, raw: bool) -> Result<T, EnvError> {
+    __load_with(ident, file, raw, T::from_env)
+}
+
+// Like __load, but with a custom parse step for fields that transform the raw value first
+pub fn __load_with<T: FromEnv>(ident: &str, file: bool, raw: bool, parse: impl FnOnce(&str) -> Result<T, EnvErrorType>) -> Result<T, EnvError> {
+    let read = __read(ident, file, raw).map_err(|err| if T::is_secret() { __mask_error(err) } else { err })?;
+    __parse_read(ident, read, parse)
+}
+
+// Backs #[utils(file_only)]: only the _FILE var is read, and a plain var is refused so a secret
+// can't be handed over through the environment by accident
+pub fn __load_file_only<T: FromEnv>(ident: &str, raw: bool) -> Result<T, EnvError> {
+    if !matches!(__var(ident), Err(std::env::VarError::NotPresent)) {
+        let message = format!("Has to be given as a file through {}, a plain value isn't accepted", __file_ident(ident));
+        return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(message), expected: None, value: None });
+    }
+
+    __load(ident, true, raw)
+}
+
+// Files load_async already read are taken from memory, everything else reads from disk
+fn __read_file(path: &str) -> std::io::Result<String> {
+    #[cfg(feature = "async")]
+    if let Some(contents) = PREFETCHED.with(|files| files.borrow().as_ref().and_then(|files| files.get(path).cloned())) {
+        return Ok(contents);
+    }
+
+    std::fs::read_to_string(path)
+}
+
+#[cfg(feature = "async")]
+thread_local! {
+    static PREFETCHED: std::cell::RefCell<Option<Arc<HashMap<String, String>>>> = const { std::cell::RefCell::new(None) };
+}
+
+// At most this many files are read at once, however many file-backed fields a config has
+#[cfg(feature = "async")]
+const PREFETCH_THREADS: usize = 8;
+
+// The files behind the set `_FILE` vars of a load, read concurrently by up to PREFETCH_THREADS
+// OS threads since std has no async file reads. Failed reads are left out, the load reads those
+// again and reports the error as usual. The same goes for the files of a thread that panics or
+// can't be spawned. Every thread is joined before this returns, none is left running.
+#[cfg(feature = "async")]
+async fn __prefetch_files<T: FromEnv>(ident: &str) -> HashMap<String, String> {
+    use futures_util::StreamExt;
+
+    let vars = T::env_vars(ident);
+    let paths: Vec<String> = vars.iter()
+        .filter(|var| vars.iter().any(|base| __file_ident(base) == **var))
+        .filter_map(|var| __var(var).ok())
+        .collect();
+
+    let threads = paths.len().min(PREFETCH_THREADS);
+    let queue = Arc::new(std::sync::Mutex::new(paths));
+    let (sender, receiver) = futures_channel::mpsc::unbounded();
+    let handles: Vec<_> = (0..threads).filter_map(|_| {
+        let (queue, sender) = (queue.clone(), sender.clone());
+        std::thread::Builder::new().name(String::from("utils-prefetch")).spawn(move || loop {
+            let next = queue.lock().unwrap_or_else(|err| err.into_inner()).pop();
+            let Some(path) = next else { break };
+            if let Ok(contents) = std::fs::read_to_string(&path) {
+                let _ = sender.unbounded_send((path, contents));
+            }
+        }).ok()
+    }).collect();
+
+    // The stream ends once every thread is done and has dropped its sender, panicking or not,
+    // so the joins below don't wait on any reads
+    drop(sender);
+    let files = receiver.collect().await;
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    files
+}
+
+#[cfg(feature = "async")]
+fn __with_prefetched<T>(files: HashMap<String, String>, load: impl FnOnce() -> T) -> T {
+    let _scope = PrefetchScope(PREFETCHED.with(|current| current.replace(Some(Arc::new(files)))));
+    load()
+}
+
+// Restores the files prefetched before __with_prefetched, also when the load panics
+#[cfg(feature = "async")]
+struct PrefetchScope(Option<Arc<HashMap<String, String>>>);
+
+#[cfg(feature = "async")]
+impl Drop for PrefetchScope {
+    fn drop(&mut self) {
+        PREFETCHED.with(|current| current.replace(self.0.take()));
+    }
+}
+
+// Like FromEnv::load, but the files of file-backed fields are read concurrently first. Plain vars are
+// still read synchronously, and errors are the same as load's: the first one, in field order.
+// The files are read on a few short-lived OS threads, not on the executor, so any executor works.
+#[cfg(feature = "async")]
+pub async fn load_async<T: FromEnv>(ident: &str) -> Result<T, EnvError> {
+    let files = __prefetch_files::<T>(ident).await;
+    __with_prefetched(files, || T::load(ident))
+}
+
+// Like load_async, but collects every error like FromEnv::load_all
+#[cfg(feature = "async")]
+pub async fn load_all_async<T: FromEnv>(ident: &str) -> Result<T, EnvErrors> {
+    let files = __prefetch_files::<T>(ident).await;
+    __with_prefetched(files, || T::load_all(ident))
+}
+
+// Backs #[utils(parse_with = "path::to::fn")], the target type doesn't have to implement FromEnv
+pub fn __load_parsed<T>(ident: &str, file: bool, raw: bool, parse: impl FnOnce(&str) -> Result<T, EnvErrorType>) -> Result<T, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) => EnvError::convert(parse(&value), ident),
+        None => Err(EnvError { var: String::from(ident), ty: EnvErrorType::NotPresent, expected: None, value: None })
+    }
+}
+
+// Backs #[utils(read_timeout = "2s")] on file-backed fields. The read runs on its own thread
+// so a hung mount can't block startup, a read that never finishes is left behind on timeout.
+// Without the attribute reads are unbounded, as before.
+pub fn __load_timeout<T: FromEnv>(ident: &str, raw: bool, timeout: &str) -> Result<T, EnvError> {
+    let timeout = EnvError::parse::<Duration>(timeout, ident)?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let name = String::from(ident);
+    let source = SOURCE.with(|source| source.borrow().clone());
+    let case_insensitive = CASE_INSENSITIVE.with(|flag| flag.get());
+    let blank_as_unset = BLANK_AS_UNSET.with(|flag| flag.get());
+    // Files load_async already read don't have to be read again on the thread
+    #[cfg(feature = "async")]
+    let prefetched = PREFETCHED.with(|files| files.borrow().clone());
+    std::thread::spawn(move || {
+        SOURCE.with(|outer| outer.replace(source));
+        CASE_INSENSITIVE.with(|flag| flag.set(case_insensitive));
+        BLANK_AS_UNSET.with(|flag| flag.set(blank_as_unset));
+        #[cfg(feature = "async")]
+        PREFETCHED.with(|files| files.replace(prefetched));
+        sender.send(__read(&name, true, raw))
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(read) => __parse_read(ident, read?, T::from_env),
+        Err(_) => Err(EnvError { var: __file_ident(ident), ty: EnvErrorType::Other(format!("Timed out reading after {:?}", timeout)), expected: None, value: None })
+    }
+}
+
+// Backs #[utils(consume)]: once a secret is loaded, whichever var it came from is removed from
+// the process environment so child processes and /proc/self/environ don't see it. Note that
+// this mutates the environment of the whole process, Masked only hides it from Debug output.
+// Under load_from the var is removed from that source instead (see EnvSource::forget).
+pub fn __consume(ident: &str, file: bool) {
+    let source = __active_source();
+    if !matches!(source.get(ident), Err(std::env::VarError::NotPresent)) {
+        source.forget(ident);
+    } else if file {
+        source.forget(&__file_ident(ident));
+    }
+}
+
+fn __error_value<T: FromEnv>(value: &str) -> Option<String> {
+    (!T::is_secret()).then(|| String::from(value))
+}
+
+fn __parse_read<T: FromEnv>(ident: &str, read: Option<String>, parse: impl FnOnce(&str) -> Result<T, EnvErrorType>) -> Result<T, EnvError> {
+    match read {
+        Some(value) => parse(&value).map_err(|ty| EnvError { var: String::from(ident), ty, expected: Some(T::expected()), value: __error_value::<T>(&value) }),
+        None => EnvError::convert(T::not_present(), ident)
+    }
+}
+
+macro_rules! impl_from_env {
+    ($($t:ty),*) => {
+        $(impl FromEnv for $t {
+            fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+                value.parse().map_err(|err: <$t as std::str::FromStr>::Err| EnvErrorType::Parse(err.to_string()))
+            }
+
+            fn type_name() -> &'static str {
+                stringify!($t)
+            }
+        })*
+    };
+}
+
+impl_from_env!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_from_env!(f32, f64, String);
+impl_from_env!(SocketAddr, SocketAddrV4, Ipv4Addr, PathBuf);
+impl_from_env!(char, NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize);
+
+// Atomics parse like the value they hold, for config that's flipped at runtime
+macro_rules! impl_from_env_atomic {
+    ($($t:ty => $inner:ty),*) => {
+        $(impl FromEnv for $t {
+            fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+                <$inner>::from_env(value).map(<$t>::new)
+            }
+
+            fn type_name() -> &'static str {
+                <$inner>::type_name()
+            }
+        })*
+    };
+}
+
+#[cfg(target_has_atomic = "8")]
+impl_from_env_atomic!(AtomicBool => bool, AtomicU8 => u8, AtomicI8 => i8);
+#[cfg(target_has_atomic = "16")]
+impl_from_env_atomic!(AtomicU16 => u16, AtomicI16 => i16);
+#[cfg(target_has_atomic = "32")]
+impl_from_env_atomic!(AtomicU32 => u32, AtomicI32 => i32);
+#[cfg(target_has_atomic = "64")]
+impl_from_env_atomic!(AtomicU64 => u64, AtomicI64 => i64);
+#[cfg(target_has_atomic = "ptr")]
+impl_from_env_atomic!(AtomicUsize => usize, AtomicIsize => isize);
+
+// IPv6 zones (`fe80::1%2`) are kept as the scope id of a SocketAddrV6, which means they have to be
+// numeric interface indexes. Named zones like `%eth0` and zones on types that can't hold one
+// (Ipv6Addr and IpAddr, which std gives no place for a scope id) are errors that say so and
+// point at SocketAddrV6, rather than std's parse error. Dropping the zone instead would
+// quietly bind a link-local address to the wrong interface. Any other bad address is a Parse
+// error with std's message, like the other address types.
+impl FromEnv for SocketAddrV6 {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|err: std::net::AddrParseError| {
+            match value.split_once('%').and_then(|(_, zone)| zone.split(']').next()) {
+                Some(zone) if !zone.is_empty() && !zone.chars().all(|ch| ch.is_ascii_digit()) => EnvErrorType::Other(format!("Zone '{}' isn't supported, expected a numeric interface index", zone)),
+                _ => EnvErrorType::Parse(err.to_string())
+            }
+        })
+    }
+
+    fn type_name() -> &'static str {
+        "SocketAddrV6"
+    }
+}
+
+impl FromEnv for Ipv6Addr {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        match value.split_once('%') {
+            Some((addr, _)) if addr.parse::<Ipv6Addr>().is_ok() => Err(EnvErrorType::Other(format!("'{}' has a zone, which Ipv6Addr can't hold, use SocketAddrV6 instead", value))),
+            _ => value.parse().map_err(|err: std::net::AddrParseError| EnvErrorType::Parse(err.to_string()))
+        }
+    }
+
+    fn type_name() -> &'static str {
+        "Ipv6Addr"
+    }
+}
+
+impl FromEnv for IpAddr {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        match value.split_once('%') {
+            Some((addr, _)) if addr.parse::<Ipv6Addr>().is_ok() => Err(EnvErrorType::Other(format!("'{}' has a zone, which IpAddr can't hold, use SocketAddrV6 instead", value))),
+            _ => value.parse().map_err(|err: std::net::AddrParseError| EnvErrorType::Parse(err.to_string()))
+        }
+    }
+
+    fn type_name() -> &'static str {
+        "IpAddr"
+    }
+}
+
+// Backs #[utils(presets = "...")], e.g. `short=1s,long=30s` on a Duration field
+pub fn __preset<'a>(value: &'a str, presets: &[(&'static str, &'static str)]) -> &'a str {
+    presets.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value.trim()))
+        .map_or(value, |(_, preset)| preset)
+}
+
+// Backs #[utils(duration_unit = "...")], a bare number gets the unit appended before it's parsed
+pub fn __duration_unit(value: &str, unit: &str) -> String {
+    let trimmed = value.trim();
+    if !trimmed.is_empty() && trimmed.bytes().all(|byte| byte.is_ascii_digit()) {
+        format!("{}{}", trimmed, unit)
+    } else {
+        String::from(value)
+    }
+}
+
+// Besides true/false, bools accept 1/0, yes/no, on/off, t/f and enabled/disabled, in any case.
+// The error lists the same table, so the two can't drift apart
+const BOOL_VALUES: [(&str, bool); 12] = [
+    ("true", true), ("false", false),
+    ("1", true), ("0", false),
+    ("yes", true), ("no", false),
+    ("on", true), ("off", false),
+    ("t", true), ("f", false),
+    ("enabled", true), ("disabled", false)
+];
+
+impl FromEnv for bool {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let value = value.trim();
+        BOOL_VALUES.iter()
+            .find(|(name, _)| value.eq_ignore_ascii_case(name))
+            .map(|(_, flag)| *flag)
+            .ok_or(EnvErrorType::InvalidFormat)
+    }
+
+    fn type_name() -> &'static str {
+        "bool"
+    }
+
+    fn expected() -> &'static str {
+        static EXPECTED: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        EXPECTED.get_or_init(|| {
+            let names: Vec<String> = BOOL_VALUES.iter().map(|(name, _)| format!("'{}'", name)).collect();
+            format!("one of {}", names.join(", "))
+        })
+    }
+}
+
+// The `COLOR=auto|always|never` pattern, on and off work as always and never. Auto is left for
+// the caller to decide, e.g. `resolve(stdout.is_terminal())`.
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoBool {
+    Auto,
+    Always,
+    Never
+}
+
+impl AutoBool {
+    pub fn resolve(self, is_tty: bool) -> bool {
+        match self {
+            AutoBool::Auto => is_tty,
+            AutoBool::Always => true,
+            AutoBool::Never => false
+        }
+    }
+}
+
+impl FromEnv for AutoBool {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(AutoBool::Auto),
+            "always" | "on" => Ok(AutoBool::Always),
+            "never" | "off" => Ok(AutoBool::Never),
+            _ => Err(EnvErrorType::InvalidFormat)
+        }
+    }
+
+    fn type_name() -> &'static str {
+        "AutoBool"
+    }
+}
+
+impl fmt::Display for AutoBool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoBool::Auto => write!(f, "auto"),
+            AutoBool::Always => write!(f, "always"),
+            AutoBool::Never => write!(f, "never")
+        }
+    }
+}
+
+// Field names #[utils(check_secrets)] treats as secrets unless `secret_patterns` replaces them
+pub const SECRET_PATTERNS: &[&str] = &["password", "secret", "token", "key"];
+
+// Backs #[utils(check_secrets)]: a field whose name contains one of the patterns but whose type
+// isn't secret (e.g. a String instead of a Masked<String>) is logged as a tracing warning (with
+// the tracing feature, dropped without it), or with `check_secrets = "error"` fails the load
+pub fn __check_secret(field: &str, ident: &str, is_secret: bool, patterns: &[&str], deny: bool) -> Result<(), EnvError> {
+    let Some(message) = __secret_warning(field, is_secret, patterns) else { return Ok(()); };
+    if deny {
+        return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(message), expected: None, value: None });
+    }
+
+    #[cfg(feature = "tracing")]
+    tracing::warn!(var = ident, "{}", message);
+    Ok(())
+}
+
+fn __secret_warning(field: &str, is_secret: bool, patterns: &[&str]) -> Option<String> {
+    let field_lower = field.to_lowercase();
+    let pattern = patterns.iter().find(|pattern| field_lower.contains(*pattern))?;
+    (!is_secret).then(|| format!("Field '{}' looks like a secret ('{}') but isn't masked", field, pattern))
+}
+
+// Backs #[utils(dedup)], keeping the first of each value so the order they were written in survives
+pub fn __dedup<T: PartialEq>(values: &mut Vec<T>) {
+    let mut index = 0;
+    while index < values.len() {
+        if values[..index].contains(&values[index]) {
+            values.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+// Backs #[utils(min = ..., max = ...)], the bounds are parsed like the field itself so any type
+// with an ordering works, e.g. `max = "1h"` on a Duration. Errors name the bound, not the value.
+pub fn __check_bounds<T: FromEnv + PartialOrd>(ident: &str, value: &T, min: Option<&str>, max: Option<&str>) -> Result<(), EnvError> {
+    let bound = |bound: &str| EnvError::parse::<T>(bound, ident);
+    if let Some(min) = min {
+        if *value < bound(min)? {
+            return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(format!("Must be at least {}", min)), expected: None, value: None });
+        }
+    }
+
+    if let Some(max) = max {
+        if *value > bound(max)? {
+            return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(format!("Must be at most {}", max)), expected: None, value: None });
+        }
+    }
+
+    Ok(())
+}
+
+// Backs #[utils(allowed = ["info", "warn"])], for deployments that only permit some of a type's
+// values. The allowed values are parsed like the field, so any spelling the type accepts works.
+// One that doesn't parse is a mistake in the attribute rather than the environment, so it's an
+// error whatever the var is set to.
+pub fn __check_allowed<T: FromEnv + PartialEq>(ident: &str, value: &T, allowed: &[&str]) -> Result<(), EnvError> {
+    let mut candidates: Vec<T> = Vec::new();
+    for candidate in allowed {
+        match T::from_env(candidate) {
+            Ok(candidate) => candidates.push(candidate),
+            Err(_) => {
+                let message = format!("#[utils(allowed)] lists '{}', which isn't a valid {}", candidate, T::type_name());
+                return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(message), expected: None, value: None });
+            }
+        }
+    }
+
+    if candidates.contains(value) {
+        return Ok(());
+    }
+
+    let allowed: Vec<String> = allowed.iter().map(|allowed| format!("'{}'", allowed)).collect();
+    Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(format!("Must be one of {}", allowed.join(", "))), expected: None, value: None })
+}
+
+// Backs #[utils(empty_is_true)], for the `FLAG=` convention where being set at all means true
+pub fn __load_flag(ident: &str, file: bool, raw: bool) -> Result<bool, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) if value.trim().is_empty() => Ok(true),
+        Some(value) => EnvError::parse(&value, ident),
+        None => Ok(false)
+    }
+}
+
+// Backs #[utils(zero_is_none)] on Option<Duration> and Option<integer> fields, for the "0 disables"
+// convention. Anything that parses to T's default (0, "0s", "0ms") loads as None.
+pub fn __load_zero_is_none<T: FromEnv + Default + PartialEq>(ident: &str, file: bool, raw: bool) -> Result<Option<T>, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) => EnvError::parse::<T>(&value, ident).map(|value| Some(value).filter(|value| *value != T::default())),
+        None => Ok(None)
+    }
+}
+
+// Renders a Duration the way it would be written in a var, in the largest unit that keeps it exact
+pub fn __duration_value(duration: &Duration) -> String {
+    match duration {
+        duration if *duration == Duration::MAX => String::from("never"),
+        duration if duration.subsec_nanos() == 0 => format!("{}s", duration.as_secs()),
+        duration if duration.subsec_nanos() % 1_000_000 == 0 => format!("{}ms", duration.as_millis()),
+        duration => format!("{:?}", duration)
+    }
+}
+
+// Durations are a number with an optional unit (ms, s, m, h, d), defaulting to seconds.
+// "never", "infinite" and "none" mean "no timeout" and load as Duration::MAX, while "0" is
+// Duration::ZERO like any other bare number of seconds.
+impl FromEnv for Duration {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let value = value.trim();
+        if ["never", "infinite", "none"].iter().any(|sentinel| value.eq_ignore_ascii_case(sentinel)) {
+            return Ok(Duration::MAX);
+        }
+
+        if value.starts_with('-') {
+            return Err(EnvErrorType::Other(format!("Durations can't be negative, found '{}'", value)));
+        }
+
+        // Dots are kept in the number so "1.2.3" reads as a bad number rather than a bad unit
+        let split = value.find(|ch: char| !ch.is_ascii_digit() && ch != '.').unwrap_or(value.len());
+        let (amount, unit) = value.split_at(split);
+        let amount: u64 = amount.parse().map_err(|_| EnvErrorType::Other(format!("Invalid number '{}'", if amount.is_empty() { value } else { amount })))?;
+        let multiplier = match unit.trim() {
+            "ms" => return Ok(Duration::from_millis(amount)),
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            unit => { return Err(EnvErrorType::Other(format!("Unknown duration unit '{}', expected one of ms, s, m, h or d", unit))); }
+        };
+
+        amount.checked_mul(multiplier).map(Duration::from_secs).ok_or(EnvErrorType::InvalidFormat)
+    }
+
+    fn type_name() -> &'static str {
+        "Duration"
+    }
+}
+
+// Retry backoff and the like, `BACKOFF=1s..3s` is anywhere from 1s to 3s and a single duration
+// means no jitter. Both ends use the Duration format.
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterDuration {
+    pub min: Duration,
+    pub max: Duration
+}
+
+impl JitterDuration {
+    #[cfg(feature = "rand")]
+    pub fn sample(&self) -> Duration {
+        if self.min == self.max {
+            return self.min;
+        }
+
+        rand::Rng::gen_range(&mut rand::thread_rng(), self.min..=self.max)
+    }
+}
+
+impl fmt::Display for JitterDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            return write!(f, "{}", __duration_value(&self.min));
+        }
+
+        write!(f, "{}..{}", __duration_value(&self.min), __duration_value(&self.max))
+    }
+}
+
+impl FromEnv for JitterDuration {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let Some((min, max)) = value.split_once("..") else {
+            let value = Duration::from_env(value)?;
+            return Ok(JitterDuration { min: value, max: value });
+        };
+
+        let (min, max) = (Duration::from_env(min)?, Duration::from_env(max)?);
+        if min > max {
+            return Err(EnvErrorType::Other(String::from("The start of the range can't be after the end")));
+        }
+
+        Ok(JitterDuration { min, max })
+    }
+
+    fn type_name() -> &'static str {
+        "JitterDuration"
+    }
+}
+
+// Throttling limits like `RATE_LIMIT=100/s` or `1000/m`: a count of events per second, minute, hour
+// or day, or per any other period written as a Duration, e.g. `5/30s`. The count has to be at least
+// 1 and the period longer than 0, which Rate::new checks too.
+#[derive(fmt::Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    count: NonZeroU32,
+    period: Duration
+}
+
+impl Rate {
+    pub fn new(count: u32, period: Duration) -> Option<Self> {
+        let count = NonZeroU32::new(count)?;
+        (!period.is_zero()).then_some(Rate { count, period })
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.get()
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    // Time between two events at this rate
+    pub fn interval(&self) -> Duration {
+        self.period / self.count.get()
+    }
+
+    pub fn per_second(&self) -> f64 {
+        self.count.get() as f64 / self.period.as_secs_f64()
+    }
+}
+
+// The period is written as a unit when it's exactly one, otherwise as the Duration it is, so the
+// output parses back to the same Rate
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.period.as_secs(), self.period.subsec_nanos()) {
+            (1, 0) => write!(f, "{}/s", self.count),
+            (60, 0) => write!(f, "{}/m", self.count),
+            (3600, 0) => write!(f, "{}/h", self.count),
+            (86400, 0) => write!(f, "{}/d", self.count),
+            _ => write!(f, "{}/{}", self.count, __duration_value(&self.period))
+        }
+    }
+}
+
+impl FromEnv for Rate {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let (count, period) = value.split_once('/').ok_or(EnvErrorType::InvalidFormat)?;
+        let count: u32 = count.trim().parse().map_err(|_| EnvErrorType::InvalidFormat)?;
+        if count == 0 {
+            return Err(EnvErrorType::Other(String::from("The count has to be at least 1")));
+        }
+
+        let period = match period.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(60 * 60),
+            "d" => Duration::from_secs(24 * 60 * 60),
+            period => Duration::from_env(period)?
+        };
+
+        Rate::new(count, period).ok_or_else(|| EnvErrorType::Other(String::from("The period has to be longer than 0")))
+    }
+
+    fn type_name() -> &'static str {
+        "Rate"
+    }
+}
+
+// humantime accepts compound spans ("1h 30m", "2days 4h") and RFC 3339 timestamps,
+// so it's the better fit for users who already write their durations in that format.
+#[cfg(feature = "humantime")]
+impl FromEnv for humantime::Duration {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "humantime")]
+impl FromEnv for humantime::Timestamp {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "jiff")]
+impl FromEnv for jiff::Timestamp {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "jiff")]
+impl FromEnv for jiff::Zoned {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "jiff")]
+impl FromEnv for jiff::Span {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.parse().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+// Timestamps are RFC 3339 and dates are `YYYY-MM-DD`. Durations use the same format as
+// std Duration, bar "never" and friends since time::Duration has no "no timeout" value.
+#[cfg(feature = "time")]
+impl FromEnv for time::OffsetDateTime {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        time::OffsetDateTime::parse(value.trim(), &time::format_description::well_known::Rfc3339).map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "time")]
+impl FromEnv for time::Date {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        time::Date::parse(value.trim(), time::macros::format_description!("[year]-[month]-[day]")).map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+#[cfg(feature = "time")]
+impl FromEnv for time::Duration {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        Duration::from_env(value)?.try_into().map_err(|_| EnvErrorType::InvalidFormat)
+    }
+}
+
+// A var that's set but empty (after trimming) is treated like an unset one, T isn't parsed.
+// Types reading several vars (derived structs and enums) are Some once any of their vars is set,
+// and then load like they would outside of the Option, so a missing field is still an error.
+impl<T> FromEnv for Option<T> where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        if value.trim().is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(T::from_env(value)?))
+    }
+
+    fn load(ident: &str) -> Result<Self, EnvError> {
+        let vars = T::env_vars(ident);
+        if vars.len() == 1 && vars[0] == ident {
+            return __load(ident, false, false);
+        }
+
+        match vars.iter().any(|var| __is_set(var)) {
+            true => T::load(ident).map(Some),
+            false => Ok(None)
+        }
+    }
+
+    fn env_vars(ident: &str) -> Vec<String> {
+        T::env_vars(ident)
+    }
+
+    fn env_names_into(ident: &str, path: &str, names: &mut Vec<(String, String)>) {
+        T::env_names_into(ident, path, names);
+    }
+
+    fn help_into(ident: &str, _required: bool, file: bool, lines: &mut Vec<String>) {
+        T::help_into(ident, false, file, lines);
+    }
+
+    #[cfg(feature = "serde")]
+    fn schema_into(ident: &str) -> serde_json::Value {
+        T::schema_into(ident)
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+
+    fn type_name() -> &'static str {
+        T::type_name()
+    }
+
+    fn expected() -> &'static str {
+        T::expected()
+    }
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        Ok(None)
+    }
+}
+
+// Lists split on commas unless a field sets #[utils(delimiter = "...")] and elements are trimmed.
+// An empty value is an empty list and a trailing delimiter is harmless, so `a,b,` is ["a", "b"].
+// Empty elements in between are parsed, so `a,,b` is ["a", "", "b"] as a Vec<String> and an
+// error as a Vec<u16>. Use #[utils(skip_empty)] to drop those too.
+impl<T> FromEnv for Vec<T> where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        __parse_list(value, ",", false)
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+}
+
+// Fixed-size lists split like Vec does, but the number of elements has to be exactly N
+impl<T, const N: usize> FromEnv for [T; N] where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let values: Vec<T> = __parse_list(value, ",", false)?;
+        let len = values.len();
+        values.try_into().map_err(|_| EnvErrorType::Other(format!("Expected {} elements, found {}", N, len)))
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+}
+
+// A bad element is named by its (0-based) index and, unless T is secret, its text, e.g.
+// "Invalid element 'bad' at index 2: invalid socket address syntax"
+pub fn __parse_list<T: FromEnv>(value: &str, delimiter: &str, skip_empty: bool) -> Result<Vec<T>, EnvErrorType> {
+    if value.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut elements: Vec<&str> = value.split(delimiter).map(str::trim).collect();
+    if elements.last() == Some(&"") {
+        elements.pop();
+    }
+
+    elements.into_iter()
+        .filter(|element| !skip_empty || !element.is_empty())
+        .enumerate()
+        .map(|(index, element)| T::from_env(element).map_err(|err| {
+            let element = if T::is_secret() { String::new() } else { format!(" '{}'", element) };
+            let reason = match err {
+                EnvErrorType::Parse(reason) | EnvErrorType::Other(reason) => reason,
+                _ => format!("expected {}", T::type_name())
+            };
+
+            EnvErrorType::Other(format!("Invalid element{} at index {}: {}", element, index, reason))
+        }))
+        .collect()
+}
+
+impl<T> FromEnv for HashSet<T> where T: FromEnv + Eq + Hash {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        value.split(',')
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .map(T::from_env)
+            .collect()
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+}
+
+// Backs #[utils(flag_set)]: every listed name maps to true, and names from the
+// `universe` attribute (e.g. the variants of a feature enum) default to false.
+// With a universe set, listing a name outside of it is an error.
+pub fn __load_flag_set(ident: &str, file: bool, universe: &[&str]) -> Result<HashMap<String, bool>, EnvError> {
+    let mut flags: HashMap<String, bool> = universe.iter().map(|name| (String::from(*name), false)).collect();
+    let enabled = match __read(ident, file, false)? {
+        Some(value) => EnvError::parse::<HashSet<String>>(&value, ident)?,
+        None => HashSet::new()
+    };
+
+    for name in enabled {
+        if !universe.is_empty() && !flags.contains_key(&name) {
+            return Err(EnvError { var: String::from(ident), ty: EnvErrorType::Other(format!("Unknown flag '{}'", name)), expected: None, value: None });
+        }
+
+        flags.insert(name, true);
+    }
+
+    Ok(flags)
+}
+
+fn __parse_entries<'a, V: FromEnv>(value: &'a str, pair_sep: &'a str, entry_sep: &'a str) -> impl Iterator<Item = Result<(String, V), EnvErrorType>> + 'a {
+    value.split(entry_sep)
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(move |entry| match entry.split_once(pair_sep) {
+            Some((key, value)) => Ok((String::from(key.trim()), V::from_env(value.trim())?)),
+            None => Err(EnvErrorType::Other(format!("Invalid entry '{}', expected 'key{}value'", entry, pair_sep)))
+        })
+}
+
+// Backs `pair_sep`/`entry_sep` on map fields, e.g. `primary:host=a;port=1,replica:host=b` with pair_sep = ":"
+pub fn __parse_map<V: FromEnv, M: FromIterator<(String, V)>>(value: &str, pair_sep: &str, entry_sep: &str) -> Result<M, EnvErrorType> {
+    __parse_entries(value, pair_sep, entry_sep).collect()
+}
+
+// Derived structs parse from a single `host=a;port=1` value, each key standing in for the var of
+// the field with that name. Keys are named like the vars of load(""), under the struct's prefix and
+// joined with its separator, so nested fields use their joined name, e.g. `pool_size=5` (or
+// `pool__size=5` with a "__" separator). `renames` maps rename_all spellings to field names.
+pub fn __from_blob<T: FromEnv>(value: &str, ident: &str, pair_sep: &str, entry_sep: &str, renames: &[(&str, &str)]) -> Result<T, EnvErrorType> {
+    let vars: HashMap<String, String> = __parse_entries::<String>(value, pair_sep, entry_sep)
+        .map(|entry| entry.map(|(key, value)| {
+            let key = renames.iter().find(|(renamed, _)| *renamed == key).map_or(key.as_str(), |(_, name)| name);
+            (__join_idents(ident, key), value)
+        }))
+        .collect::<Result<_, _>>()?;
+
+    EnvSnapshot::from(vars).load("").map_err(|err| EnvErrorType::Other(err.to_string()))
+}
+
+// `pool=5,timeout=30` style maps. HashMap keeps no order and a repeated key keeps its last
+// value, use IndexMap (behind the indexmap feature) where the order entries were written matters.
+impl<V> FromEnv for HashMap<String, V> where V: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        __parse_entries(value, "=", ",").collect()
+    }
+
+    fn is_secret() -> bool {
+        V::is_secret()
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<V> FromEnv for indexmap::IndexMap<String, V> where V: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        __parse_entries(value, "=", ",").collect()
+    }
+
+    fn is_secret() -> bool {
+        V::is_secret()
+    }
+}
+
+// Integer types #[utils(overflow = "saturate" | "wrap")] can bring an out of range value back into
+pub trait Integer: Sized + ToString {
+    fn saturate(value: i128) -> Self;
+    fn wrap(value: i128) -> Self;
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(impl Integer for $t {
+            fn saturate(value: i128) -> Self {
+                value.clamp(<$t>::MIN as i128, <$t>::MAX as i128) as $t
+            }
+
+            fn wrap(value: i128) -> Self {
+                value as $t
+            }
+        })*
+    };
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Backs #[utils(overflow)], rewrites the value so it's in range for T before it's parsed.
+// Values that aren't integers at all are still InvalidFormat.
+pub fn __overflow<T: Integer>(value: &str, wrap: bool) -> Result<String, EnvErrorType> {
+    let value: i128 = value.trim().parse().map_err(|_| EnvErrorType::InvalidFormat)?;
+    Ok(if wrap { T::wrap(value) } else { T::saturate(value) }.to_string())
+}
+
+// Backs #[utils(line = N)], picking the trimmed Nth line (counting from 1) of a multi-line value
+pub fn __line(value: &str, line: usize) -> Result<&str, EnvErrorType> {
+    line.checked_sub(1)
+        .and_then(|index| value.lines().nth(index))
+        .map(str::trim)
+        .ok_or_else(|| EnvErrorType::Other(format!("Line {} doesn't exist, the value has {} lines", line, value.lines().count())))
+}
+
+// Backs #[utils(base64_value)] for platforms that base64 the whole value to carry newlines safely.
+// Standard alphabet, padding is optional and ASCII whitespace anywhere is ignored, so MIME or PEM
+// style input wrapped into lines decodes as well.
+pub fn __base64_value(value: &str) -> Result<String, EnvErrorType> {
+    let value: String = value.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let value = value.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(value.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in value.bytes() {
+        let sextet = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => { return Err(EnvErrorType::InvalidFormat); }
+        };
+
+        buffer = (buffer << 6) | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    // A single leftover sextet can't come from any encoded byte
+    if bits == 6 {
+        return Err(EnvErrorType::InvalidFormat);
+    }
+
+    String::from_utf8(bytes).map_err(|_| EnvErrorType::InvalidFormat)
+}
+
+// Backs #[utils(percent)], "25%" becomes "0.25" while values without a '%' pass through
+pub fn __percent(value: &str) -> Result<String, EnvErrorType> {
+    match value.trim().strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|percent| (percent / 100.0).to_string()).map_err(|_| EnvErrorType::InvalidFormat),
+        None => Ok(String::from(value))
+    }
+}
+
+// A ratio in [0, 1], written as either "25%" or "0.25". Values outside of the range are
+// rejected rather than clamped, so a typo like "250%" doesn't silently become 100%.
+#[derive(fmt::Debug, Clone, Copy, PartialEq)]
+pub struct Percent(pub f64);
+
+impl FromEnv for Percent {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let ratio = f64::from_env(&__percent(value)?)?;
+        if !(0.0..=1.0).contains(&ratio) {
+            return Err(EnvErrorType::Other(format!("'{}' is out of range, expected 0% to 100%", value)));
+        }
+
+        Ok(Percent(ratio))
+    }
+}
+
+impl fmt::Display for Percent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.0 * 100.0)
+    }
+}
+
+// A `RUST_LOG` style filter like `info,myapp=debug`, checked with tracing-subscriber's EnvFilter
+// when it's loaded so a malformed filter fails at startup rather than quietly logging nothing
+#[cfg(feature = "tracing-subscriber")]
+#[derive(fmt::Debug, Clone, PartialEq)]
+pub struct LogFilter(pub String);
+
+#[cfg(feature = "tracing-subscriber")]
+impl LogFilter {
+    pub fn to_env_filter(&self) -> tracing_subscriber::EnvFilter {
+        tracing_subscriber::EnvFilter::new(&self.0)
+    }
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl FromEnv for LogFilter {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        tracing_subscriber::EnvFilter::builder().parse(value)
+            .map(|_| LogFilter(String::from(value.trim())))
+            .map_err(|err| EnvErrorType::Parse(err.to_string()))
+    }
+
+    fn type_name() -> &'static str {
+        "LogFilter"
+    }
+}
+
+// Backs #[utils(multimap)], e.g. `X-Foo:a,b;X-Bar:c` with the default separators. `pair_sep`
+// splits a key from its values, `entry_sep` splits entries and `value_sep` splits the values.
+// Repeated keys extend the same list.
+pub fn __load_multimap(ident: &str, file: bool, raw: bool, pair_sep: &str, entry_sep: &str, value_sep: &str) -> Result<HashMap<String, Vec<String>>, EnvError> {
+    let value = match __read(ident, file, raw)? {
+        Some(value) => value,
+        None => { return Err(EnvError { var: String::from(ident), ty: EnvErrorType::NotPresent, expected: None, value: None }); }
+    };
+
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in value.split(entry_sep).map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (key, values) = entry.split_once(pair_sep).ok_or_else(|| EnvError { var: String::from(ident), ty: EnvErrorType::InvalidFormat, expected: None, value: None })?;
+        map.entry(String::from(key.trim()))
+            .or_default()
+            .extend(values.split(value_sep).map(str::trim).filter(|value| !value.is_empty()).map(String::from));
+    }
+
+    Ok(map)
+}
+
+// Backs #[utils(default_dir = "...")] on PathBuf fields: when unset, the path falls back to the
+// platform data dir (e.g. ~/.local/share on Linux) joined with the subpath. On platforms without
+// one the field is simply NotPresent, like it would be without the attribute.
+#[cfg(feature = "dirs")]
+pub fn __load_default_dir(ident: &str, file: bool, raw: bool, subpath: &str) -> Result<PathBuf, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) => EnvError::parse(&value, ident),
+        None => dirs::data_dir()
+            .map(|dir| dir.join(subpath))
+            .ok_or_else(|| EnvError { var: String::from(ident), ty: EnvErrorType::NotPresent, expected: None, value: None })
+    }
+}
+
+// Backs #[utils(json)], for structured values like `[{"host":"a","port":1}]` passed in a single var
+#[cfg(feature = "serde")]
+pub fn __load_json<T: serde::de::DeserializeOwned>(ident: &str, file: bool, raw: bool) -> Result<T, EnvError> {
+    __load_json_opt(ident, file, raw)?.ok_or_else(|| EnvError { var: String::from(ident), ty: EnvErrorType::NotPresent, expected: None, value: None })
+}
+
+// #[utils(json)] on an Option<T> field, an unset var is None rather than an error
+#[cfg(feature = "serde")]
+pub fn __load_json_opt<T: serde::de::DeserializeOwned>(ident: &str, file: bool, raw: bool) -> Result<Option<T>, EnvError> {
+    __read(ident, file, raw)?
+        .map(|value| serde_json::from_str(&value).map_err(|err| EnvError { var: String::from(ident), ty: EnvErrorType::Other(err.to_string()), expected: None, value: None }))
+        .transpose()
+}
+
+// Backs #[utils(auto_json)] on Vec<T>: a value that starts with `[` once trimmed is a JSON array,
+// anything else is the usual comma separated list
+#[cfg(feature = "serde")]
+pub fn __load_auto_json<T: FromEnv + serde::de::DeserializeOwned>(ident: &str, file: bool, raw: bool) -> Result<Vec<T>, EnvError> {
+    __load_with(ident, file, raw, |value| {
+        if value.trim_start().starts_with('[') {
+            serde_json::from_str(value).map_err(|err| EnvErrorType::Other(err.to_string()))
+        } else {
+            __parse_list(value, ",", false)
+        }
+    })
+}
+
+// Backs #[utils(numbered_from = N)] on Vec<T>, for the `ORIGIN`, `ORIGIN_2`, `ORIGIN_3` convention:
+// the bare var is element number N, then `{ident}_{N + 1}`, `{ident}_{N + 2}` and so on follow.
+// The list ends at the first var that isn't set, so an unset bare var is an empty list.
+pub fn __load_numbered<T: FromEnv>(ident: &str, start: usize) -> Result<Vec<T>, EnvError> {
+    let mut values = Vec::new();
+    let mut name = String::from(ident);
+    while __is_set(&name) {
+        values.push(T::load(&name)?);
+        name = __join_idents(ident, &(start + values.len()).to_string());
+    }
+
+    Ok(values)
+}
+
+// Deduplicates strings for #[utils(intern)] fields (Arc<str> or Vec<Arc<str>>). Every distinct
+// value is allocated once and shared: a thousand fields holding the same 40 byte URL cost one
+// 40 byte allocation plus a 16 byte Arc each, rather than a thousand 40 byte Strings with their
+// own 24 byte headers. Loading through StringPool::load shares the pool across the whole config,
+// otherwise each field only dedupes within itself.
+#[derive(fmt::Debug, Default)]
+pub struct StringPool {
+    strings: HashSet<Arc<str>>
+}
+
+thread_local! {
+    static POOL: std::cell::RefCell<Option<StringPool>> = const { std::cell::RefCell::new(None) };
+}
+
+impl StringPool {
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(value) {
+            return interned.clone();
+        }
+
+        let interned: Arc<str> = Arc::from(value);
+        self.strings.insert(interned.clone());
+        interned
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    pub fn load<T: FromEnv>(&mut self, ident: &str) -> Result<T, EnvError> {
+        let outer = POOL.with(|pool| pool.replace(Some(std::mem::take(self))));
+        let _scope = PoolScope { pool: self, outer };
+        T::load(ident)
+    }
+}
+
+// Hands the pool back to StringPool::load's caller and restores the outer pool, also when the load panics
+struct PoolScope<'a> {
+    pool: &'a mut StringPool,
+    outer: Option<StringPool>
+}
+
+impl Drop for PoolScope<'_> {
+    fn drop(&mut self) {
+        *self.pool = POOL.with(|pool| pool.replace(self.outer.take())).unwrap_or_default();
+    }
+}
+
+pub trait Intern: Sized {
+    fn intern(value: &str, pool: &mut StringPool) -> Self;
+}
+
+impl Intern for Arc<str> {
+    fn intern(value: &str, pool: &mut StringPool) -> Self {
+        pool.intern(value)
+    }
+}
+
+impl Intern for Vec<Arc<str>> {
+    fn intern(value: &str, pool: &mut StringPool) -> Self {
+        value.split(',')
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .map(|element| pool.intern(element))
+            .collect()
+    }
+}
+
+pub fn __load_interned<T: Intern>(ident: &str, file: bool, raw: bool) -> Result<T, EnvError> {
+    let value = match __read(ident, file, raw)? {
+        Some(value) => value,
+        None => { return Err(EnvError { var: String::from(ident), ty: EnvErrorType::NotPresent, expected: None, value: None }); }
+    };
+
+    Ok(POOL.with(|pool| match pool.borrow_mut().as_mut() {
+        Some(pool) => T::intern(&value, pool),
+        None => T::intern(&value, &mut StringPool::default())
+    }))
+}
+
+// Backs #[utils(blob_var = "...")], for platforms that inject the whole config as one JSON var.
+// None means the var isn't set and the struct should be loaded field by field instead.
+#[cfg(feature = "serde")]
+pub fn __load_blob<T: serde::de::DeserializeOwned>(ident: &str) -> Result<Option<T>, EnvError> {
+    match __read(ident, false, false)? {
+        Some(_) => __load_json(ident, false, false).map(Some),
+        None => Ok(None)
+    }
+}
+
+// Backs #[utils(indexed)] on [T; N], every element from `{ident}_0` to `{ident}_{N - 1}` must be present
+pub fn __load_indexed<T: FromEnv, const N: usize>(ident: &str) -> Result<[T; N], EnvError> {
+    let mut values = Vec::with_capacity(N);
+    for index in 0..N {
+        values.push(T::load(&__join_idents(ident, &index.to_string()))?);
+    }
+
+    match values.try_into() {
+        Ok(values) => Ok(values),
+        Err(_) => unreachable!()
+    }
+}
+
+// Decodes fixed-size keys from hex for fields marked #[utils(hex)]
+pub trait FromHex where Self: Sized {
+    fn from_hex(value: &str) -> Result<Self, EnvErrorType>;
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        Err(EnvErrorType::NotPresent)
+    }
+}
+
+impl<const N: usize> FromHex for [u8; N] {
+    fn from_hex(value: &str) -> Result<Self, EnvErrorType> {
+        let value = value.as_bytes();
+        if value.len() != N * 2 {
+            return Err(EnvErrorType::InvalidFormat);
+        }
+
+        let mut bytes = [0; N];
+        for (byte, pair) in bytes.iter_mut().zip(value.chunks(2)) {
+            let hex = std::str::from_utf8(pair).map_err(|_| EnvErrorType::InvalidFormat)?;
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| EnvErrorType::InvalidFormat)?;
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl<T> FromHex for Option<T> where T: FromHex {
+    fn from_hex(value: &str) -> Result<Self, EnvErrorType> {
+        T::from_hex(value).map(Some)
+    }
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        Ok(None)
+    }
+}
+
+impl<T> FromHex for Masked<T> where T: FromHex {
+    fn from_hex(value: &str) -> Result<Self, EnvErrorType> {
+        T::from_hex(value).map(Masked)
+    }
+}
+
+pub fn __load_hex<T: FromHex>(ident: &str, file: bool, raw: bool) -> Result<T, EnvError> {
+    match __read(ident, file, raw)? {
+        Some(value) => EnvError::convert(T::from_hex(&value), ident),
+        None => EnvError::convert(T::not_present(), ident)
+    }
+}
+
+pub struct Masked<T>(pub T);
+
+impl<T> FromEnv for Masked<T> where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        T::from_env(value).map(|val| Masked(val))
+    }
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        T::not_present().map(|val| Masked(val))
+    }
+
+    fn is_secret() -> bool {
+        true
+    }
+
+    fn type_name() -> &'static str {
+        T::type_name()
+    }
+
+    fn expected() -> &'static str {
+        T::expected()
+    }
+}
+
+impl<T> From<T> for Masked<T> {
+    fn from(value: T) -> Self {
+        Masked(value)
+    }
+}
+
+impl<T> fmt::Debug for Masked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl<T> fmt::Display for Masked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl<T> Masked<T> {
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// Keeps the name of the var a value was loaded from, so validation further down the line can
+// say which var was wrong. Only loads record the name, a bare from_env leaves it empty.
+#[derive(fmt::Debug, Clone, PartialEq)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub var: String
+}
+
+impl<T> FromEnv for Sourced<T> where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        T::from_env(value).map(|value| Sourced { value, var: String::new() })
+    }
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        T::not_present().map(|value| Sourced { value, var: String::new() })
+    }
+
+    fn load(ident: &str) -> Result<Self, EnvError> {
+        T::load(ident).map(|value| Sourced { value, var: String::from(ident) })
+    }
+
+    fn load_or_file(ident: &str) -> Result<Self, EnvError> {
+        T::load_or_file(ident).map(|value| Sourced { value, var: String::from(ident) })
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+
+    fn type_name() -> &'static str {
+        T::type_name()
+    }
+
+    fn expected() -> &'static str {
+        T::expected()
+    }
+}
+
+impl<T> std::ops::Deref for Sourced<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+// Masked can't wipe every T on drop, so secrets that should be cleared from memory are loaded as
+// e.g. Masked<Zeroizing<String>> or Masked<Zeroizing<Vec<u8>>>, which wipe their bytes when dropped
+#[cfg(feature = "zeroize")]
+impl<T> FromEnv for zeroize::Zeroizing<T> where T: FromEnv + zeroize::Zeroize {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        T::from_env(value).map(zeroize::Zeroizing::new)
+    }
+
+    fn not_present() -> Result<Self, EnvErrorType> {
+        T::not_present().map(zeroize::Zeroizing::new)
+    }
+
+    // Anything worth wiping from memory is worth keeping out of errors too
+    fn is_secret() -> bool {
+        true
+    }
+
+    fn type_name() -> &'static str {
+        T::type_name()
+    }
+
+    fn expected() -> &'static str {
+        T::expected()
+    }
+}
+
+// Masked wipes whatever it wraps on zeroize(), and is ZeroizeOnDrop when what it wraps is, so
+// Masked<Zeroizing<T>> can be passed where a ZeroizeOnDrop bound is asked for
+#[cfg(feature = "zeroize")]
+impl<T> zeroize::Zeroize for Masked<T> where T: zeroize::Zeroize {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T> zeroize::ZeroizeOnDrop for Masked<T> where T: zeroize::ZeroizeOnDrop {}
+
+// For deployments that want secrecy's guarantees over Masked: the value is boxed, wiped on drop and
+// only reachable through ExposeSecret. SecretSlice<T> (secrecy's SecretVec) parses like a Vec<T>.
+#[cfg(feature = "secrecy")]
+impl FromEnv for secrecy::SecretString {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        Ok(secrecy::SecretString::from(value))
+    }
+
+    fn is_secret() -> bool {
+        true
+    }
+
+    fn type_name() -> &'static str {
+        "SecretString"
+    }
+}
+
+#[cfg(feature = "secrecy")]
+impl<T> FromEnv for secrecy::SecretSlice<T> where T: FromEnv + secrecy::zeroize::Zeroize, [T]: secrecy::zeroize::Zeroize {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        Vec::<T>::from_env(value).map(secrecy::SecretSlice::from)
+    }
+
+    fn is_secret() -> bool {
+        true
+    }
+
+    fn type_name() -> &'static str {
+        "SecretSlice"
+    }
+}
+
+// Each element that fails to parse is recorded instead of failing the whole list
+#[derive(fmt::Debug)]
+pub struct Skipped {
+    pub index: usize,
+    pub value: String,
+    pub ty: EnvErrorType
+}
+
+#[derive(fmt::Debug)]
+pub struct Lenient<T> {
+    pub value: T,
+    pub skipped: Vec<Skipped>
+}
+
+impl<T> FromEnv for Lenient<Vec<T>> where T: FromEnv {
+    fn from_env(value: &str) -> Result<Self, EnvErrorType> {
+        let mut values = Vec::new();
+        let mut skipped = Vec::new();
+
+        for (index, element) in value.split(',').map(str::trim).enumerate() {
+            if element.is_empty() {
+                continue;
+            }
+
+            match T::from_env(element) {
+                Ok(val) => values.push(val),
+                Err(ty) => skipped.push(Skipped { index, value: String::from(element), ty })
+            }
+        }
+
+        Ok(Lenient { value: values, skipped })
+    }
+
+    fn is_secret() -> bool {
+        T::is_secret()
+    }
+}
+
+impl<T> std::ops::Deref for Lenient<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+// Declarative alternative to derive(FromEnv), e.g.
+//
+// env_config! {
+//     pub struct App {
+//         port: u16 = "PORT" default 8080,
+//         host: String = _ default String::from("localhost"),
+//         nested: NestedConfig
+//     }
+// }
+//
+// A quoted name is used verbatim, `_` (or no name at all) joins the prefix with
+// the field name like the derive does, and a default is used when the var is not present.
+// Only the field's own var counts, a nested struct missing one of its vars is still an error.
+#[macro_export]
+macro_rules! env_config {
+    (@name $ident:ident, $field:ident, _) => {
+        &$crate::__join_idents($ident, stringify!($field))
+    };
+    (@name $ident:ident, $field:ident, $var:literal) => {
+        $var
+    };
+    (@load $ident:ident, $field:ident, $ty:ty, [], []) => {
+        <$ty as $crate::FromEnv>::load(&$crate::__join_idents($ident, stringify!($field)))
+    };
+    (@load $ident:ident, $field:ident, $ty:ty, [$var:tt], []) => {
+        <$ty as $crate::FromEnv>::load($crate::env_config!(@name $ident, $field, $var))
+    };
+    (@load $ident:ident, $field:ident, $ty:ty, [$var:tt], [$default:expr]) => {{
+        let var: &str = $crate::env_config!(@name $ident, $field, $var);
+        match <$ty as $crate::FromEnv>::load(var) {
+            Err($crate::EnvError { ty: $crate::EnvErrorType::NotPresent, var: ref missing, .. }) if missing == var => {
+                let default: $ty = $default;
+                Ok(default)
+            },
+            res => res
+        }
+    }};
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty $(= $var:tt $(default $default:expr)?)?),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::FromEnv for $name {
+            fn from_env(_value: &str) -> Result<Self, $crate::EnvErrorType> {
+                Err($crate::EnvErrorType::Other(String::from("'from' method not implemented for env_config!")))
+            }
+
+            fn type_name() -> &'static str {
+                stringify!($name)
+            }
+
+            fn load(ident: &str) -> Result<Self, $crate::EnvError> {
+                Ok($name {
+                    $($field: $crate::env_config!(@load ident, $field, $ty, [$($var)?], [$($($default)?)?])?),*
+                })
+            }
+        }
+    };
+}
+
+// Whole-config invariants that span nested structs, e.g. a cache that must fit in memory
+// configured elsewhere. Structs opt in with #[utils(validate)] and an impl of this trait,
+// load then calls it once every field is loaded and fails with Other on an error.
+pub trait Validate {
+    fn validate(&self) -> Result<(), String>;
+}
+
+// The var each field reads from as (field path, var name), derived structs opt in with
+// #[utils(env_names)]. Nested structs list their own fields under a dotted path, fields whose
+// name depends on another var (name_from) are left out.
+pub trait EnvNames {
+    fn env_names(ident: &str) -> Vec<(String, String)>;
+}
+
+// Lets clap flags fall back to the var of the field with the same id, e.g. with
+// `clap_env(command, &Config::env_names("app"))` a missing `--port` reads APP_PORT.
+// clap reads the var when it's attached, so call this after the environment is set up.
+#[cfg(feature = "clap")]
+pub fn clap_env(mut command: clap::Command, names: &[(String, String)]) -> clap::Command {
+    for (path, var) in names {
+        if command.get_arguments().any(|arg| arg.get_id() == path) {
+            command = command.mut_arg(path, |arg| arg.env(var.clone()));
+        }
+    }
+
+    command
+}
+
+// Reports the dotted paths of fields that differ between two loaded configs,
+// derived structs opt in with #[utils(diff)] and the leaf types compare with PartialEq
+pub trait Diff {
+    fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>);
+
+    fn diff(&self, other: &Self) -> Vec<String> {
+        let mut out = Vec::new();
+        self.diff_into(other, "", &mut out);
+        out
+    }
+}
+
+macro_rules! impl_diff {
+    ($($t:ty),*) => {
+        $(impl Diff for $t {
+            fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+                if self != other {
+                    out.push(String::from(path));
+                }
+            }
+        })*
+    };
+}
+
+impl_diff!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_diff!(f32, f64, bool, String, Duration, JitterDuration, Rate, PathBuf);
+impl_diff!(char, NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize, AutoBool, Percent);
+impl_diff!(SocketAddr, SocketAddrV4, SocketAddrV6, IpAddr, Ipv4Addr, Ipv6Addr);
+#[cfg(feature = "tracing-subscriber")]
+impl_diff!(LogFilter);
+
+// Lists of a different length differ as a whole, otherwise elements are compared by index
+impl<T> Diff for Vec<T> where T: Diff {
+    fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+        if self.len() != other.len() {
+            out.push(String::from(path));
+            return;
+        }
+
+        for (index, (value, other)) in self.iter().zip(other).enumerate() {
+            value.diff_into(other, &__join_path(path, &index.to_string()), out);
+        }
+    }
+}
+
+// Keys on only one side are reported as added or removed entries, in key order
+impl<V> Diff for HashMap<String, V> where V: Diff {
+    fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+        let keys: BTreeSet<&String> = self.keys().chain(other.keys()).collect();
+        for key in keys {
+            match (self.get(key), other.get(key)) {
+                (Some(value), Some(other)) => value.diff_into(other, &__join_path(path, key), out),
+                _ => out.push(__join_path(path, key))
+            }
+        }
+    }
+}
+
+impl<T> Diff for Option<T> where T: Diff {
+    fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+        match (self, other) {
+            (Some(value), Some(other)) => value.diff_into(other, path, out),
+            (None, None) => {},
+            _ => out.push(String::from(path))
+        }
+    }
+}
+
+// Only the path is reported, so masked values never leave the comparison
+impl<T> Diff for Masked<T> where T: Diff {
+    fn diff_into(&self, other: &Self, path: &str, out: &mut Vec<String>) {
+        self.0.diff_into(&other.0, path, out)
+    }
+}
+
+// Renders a loaded config keyed by var name for admin/debug views, derived structs
+// opt in with #[utils(redact)] and Masked values always come out as "***"
+pub trait Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>);
+
+    fn to_redacted_map(&self, ident: &str) -> HashMap<String, String> {
+        let mut out = HashMap::new();
+        self.redact_into(ident, &mut out);
+        out
+    }
+}
+
+macro_rules! impl_redact {
+    ($($t:ty),*) => {
+        $(impl Redact for $t {
+            fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+                out.insert(String::from(ident), self.to_string());
+            }
+        })*
+    };
+}
+
+impl_redact!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_redact!(f32, f64, bool, String, SocketAddr, SocketAddrV4, SocketAddrV6, IpAddr, Ipv4Addr, Ipv6Addr, Percent);
+impl_redact!(char, NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize, AutoBool, JitterDuration, Rate);
+#[cfg(feature = "humantime")]
+impl_redact!(humantime::Duration, humantime::Timestamp);
+#[cfg(feature = "jiff")]
+impl_redact!(jiff::Timestamp, jiff::Zoned, jiff::Span);
+#[cfg(feature = "time")]
+impl_redact!(time::OffsetDateTime, time::Date, time::Duration);
+
+impl Redact for Duration {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), __duration_value(self));
+    }
+}
+
+impl Redact for PathBuf {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), self.display().to_string());
+    }
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl Redact for LogFilter {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), self.0.clone());
+    }
+}
+
+macro_rules! impl_redact_atomic {
+    ($($t:ty),*) => {
+        $(impl Redact for $t {
+            fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+                out.insert(String::from(ident), self.load(std::sync::atomic::Ordering::Relaxed).to_string());
+            }
+        })*
+    };
+}
+
+#[cfg(target_has_atomic = "8")]
+impl_redact_atomic!(AtomicBool, AtomicU8, AtomicI8);
+#[cfg(target_has_atomic = "16")]
+impl_redact_atomic!(AtomicU16, AtomicI16);
+#[cfg(target_has_atomic = "32")]
+impl_redact_atomic!(AtomicU32, AtomicI32);
+#[cfg(target_has_atomic = "64")]
+impl_redact_atomic!(AtomicU64, AtomicI64);
+#[cfg(target_has_atomic = "ptr")]
+impl_redact_atomic!(AtomicUsize, AtomicIsize);
+
+// Unset optional values are left out of the map
+impl<T> Redact for Option<T> where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        if let Some(value) = self {
+            value.redact_into(ident, out);
+        }
+    }
+}
+
+impl<T> Redact for Masked<T> {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), self.to_string());
+    }
+}
+
+#[cfg(feature = "secrecy")]
+impl<S> Redact for secrecy::SecretBox<S> where S: secrecy::zeroize::Zeroize + ?Sized {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), String::from("***"));
+    }
+}
+
+// Lists and maps give an entry per element, keyed by index or key under the field's var
+impl<T, const N: usize> Redact for [T; N] where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        for (index, value) in self.iter().enumerate() {
+            value.redact_into(&__join_idents(ident, &index.to_string()), out);
+        }
+    }
+}
+
+impl<T> Redact for Vec<T> where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        for (index, value) in self.iter().enumerate() {
+            value.redact_into(&__join_idents(ident, &index.to_string()), out);
+        }
+    }
+}
+
+// Sets have no order to index by, so their elements are joined back into one value
+impl<T> Redact for HashSet<T> where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        let values: Vec<String> = self.iter().filter_map(|value| value.to_redacted_map(ident).remove(ident)).collect();
+        out.insert(String::from(ident), values.join(","));
+    }
+}
+
+impl<V> Redact for HashMap<String, V> where V: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        for (key, value) in self {
+            value.redact_into(&__join_idents(ident, key), out);
+        }
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<V> Redact for indexmap::IndexMap<String, V> where V: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        for (key, value) in self {
+            value.redact_into(&__join_idents(ident, key), out);
+        }
+    }
+}
+
+impl<T> Redact for Sourced<T> where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        self.value.redact_into(ident, out);
+    }
+}
+
+impl<T> Redact for Lenient<T> where T: Redact {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        self.value.redact_into(ident, out);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<T> Redact for zeroize::Zeroizing<T> where T: zeroize::Zeroize {
+    fn redact_into(&self, ident: &str, out: &mut HashMap<String, String>) {
+        out.insert(String::from(ident), String::from("***"));
+    }
+}
+
+// Renders a loaded config as JSON keyed by field name, derived structs opt in with
+// #[utils(to_json)] and become objects. Masked values come out as "***", unset ones as null.
+#[cfg(feature = "serde")]
+pub trait ToJson {
+    fn to_json(&self) -> serde_json::Value;
+}
+
+#[cfg(feature = "serde")]
+macro_rules! impl_to_json {
+    ($($t:ty),*) => {
+        $(impl ToJson for $t {
+            fn to_json(&self) -> serde_json::Value {
+                serde_json::json!(self)
+            }
+        })*
+    };
+}
+
+#[cfg(feature = "serde")]
+impl_to_json!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+#[cfg(feature = "serde")]
+impl_to_json!(f32, f64, bool, String, SocketAddr, SocketAddrV4, SocketAddrV6, IpAddr, Ipv4Addr, Ipv6Addr);
+#[cfg(feature = "serde")]
+impl_to_json!(char, NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroUsize);
+
+// Durations and paths come out as strings, written the way a var would give them
+#[cfg(feature = "serde")]
+impl ToJson for Duration {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::from(__duration_value(self))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ToJson for PathBuf {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::from(self.display().to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ToJson for JitterDuration {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::from(self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ToJson for Rate {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::from(self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> ToJson for Sourced<T> where T: ToJson {
+    fn to_json(&self) -> serde_json::Value {
+        self.value.to_json()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ToJson for Percent {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> ToJson for Option<T> where T: ToJson {
+    fn to_json(&self) -> serde_json::Value {
+        self.as_ref().map_or(serde_json::Value::Null, ToJson::to_json)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> ToJson for Masked<T> {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::from(self.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> ToJson for Vec<T> where T: ToJson {
+    fn to_json(&self) -> serde_json::Value {
+        self.iter().map(ToJson::to_json).collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T, const N: usize> ToJson for [T; N] where T: ToJson {
+    fn to_json(&self) -> serde_json::Value {
+        self.iter().map(ToJson::to_json).collect()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<V> ToJson for HashMap<String, V> where V: ToJson {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::Object(self.iter().map(|(key, value)| (key.clone(), value.to_json())).collect())
+    }
+}
+
+pub fn __join_path(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        String::from(field)
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+// Runtime prefixes like "my-app" or "my.app" are normalized to "MY_APP", since
+// anything other than alphanumerics and underscores doesn't belong in a var name.
+fn __convert_ident(ident: impl Iterator<Item = char>) -> impl Iterator<Item = char> {
+    ident
+        .flat_map(|ch| ch.to_uppercase())
+        .map(|ch| if ch.is_alphanumeric() { ch } else { '_' })
+}
+
+// What errors about a whole struct (finalize, validate) name as their var: the ident it was
+// loaded under, or for an empty one the vars it reads, which would otherwise be an empty name
+pub fn __struct_var<T: FromEnv>(ident: &str) -> String {
+    if ident.is_empty() {
+        T::env_vars(ident).join(", ")
+    } else {
+        String::from(ident)
+    }
+}
+
+// Backs #[utils(prefix = "...")], so `load("")` reads `APP_ID` and `load("eu")` reads `APP_EU_ID`
+pub fn __prefix_ident(prefix: &str, ident: &str) -> String {
+    if ident.is_empty() {
+        __convert_ident(prefix.chars()).collect()
+    } else {
+        __join_idents(prefix, ident)
+    }
+}
+
+pub fn __join_idents(ident: &str, postfix: &str) -> String {
+    if ident.is_empty() {
+        __convert_ident(postfix.chars())
+            .collect()
+    } else {
+        __convert_ident(ident.chars())
+            .chain(SEPARATOR.with(|separator| separator.get()).chars())
+            .chain(__convert_ident(postfix.chars()))
+            .collect()
+    }
+}
+
+// Backs #[utils(separator = "__")]: idents are joined with the separator until the scope is dropped
+pub fn __separator_scope(separator: &'static str) -> SeparatorScope {
+    SeparatorScope(SEPARATOR.with(|outer| outer.replace(separator)))
+}
+
+// Restores the separator that was in use before __separator_scope
+pub struct SeparatorScope(&'static str);
+
+impl Drop for SeparatorScope {
+    fn drop(&mut self) {
+        SEPARATOR.with(|separator| separator.set(self.0));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct NestedConfig {
+        host: String,
+        address: Option<Masked<String>>
+    }
+    
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct TestConfig {
+        id: i32,
+        #[utils(var_or_file, name = "TEST_NAME")]
+        name: String,
+        guest_id: Option<u64>,
+        nested: NestedConfig
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_regular() {
+        std::env::set_var("ID", "5");
+        std::env::remove_var("TEST_NAME_FILE");
+        std::env::set_var("TEST_NAME", "john doe");
+        std::env::set_var("GUEST_ID", "4");
+        std::env::set_var("NESTED_HOST", "yoyo");
+
+        let config = TestConfig::load("").expect("Config should parse correctly");
+        assert_eq!(config.id, 5);
+        assert_eq!(config.name, "john doe");
+        assert_eq!(config.guest_id, Some(4));
+        assert_eq!(config.nested.host, "yoyo");
+        assert!(config.nested.address.is_none());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_file() {
+        std::env::set_var("ID", "5");
+        std::env::set_var("GUEST_ID", "4");
+        std::env::set_var("NESTED_HOST", "yoyo");
+        std::env::remove_var("TEST_NAME");
+        std::env::set_var("TEST_NAME_FILE", "test_name.txt");
+
+        let config2 = TestConfig::load("").expect("Config should parse correctly");
+        assert_eq!(config2.name, "test");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SecretDefaultConfig {
+        #[utils(default = "8080")]
+        port: u16,
+        // Secrets can't have a default without opting in
+        #[utils(default = "dev-key", allow_default_secret)]
+        api_key: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_allow_default_secret() {
+        std::env::remove_var("SECRET_DEFAULT_PORT");
+        std::env::remove_var("SECRET_DEFAULT_API_KEY");
+        let config = SecretDefaultConfig::load("secret_default").expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.api_key.0, "dev-key");
+
+        std::env::set_var("SECRET_DEFAULT_API_KEY", "prod-key");
+        let config = SecretDefaultConfig::load("secret_default").expect("Config should parse correctly");
+        assert_eq!(config.api_key.0, "prod-key");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct DurationUnitConfig {
+        #[utils(duration_unit = "ms")]
+        timeout: std::time::Duration,
+        #[utils(duration_unit = "m")]
+        interval: Option<std::time::Duration>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_duration_unit() {
+        std::env::set_var("DURATION_UNIT_TIMEOUT", "500");
+        std::env::set_var("DURATION_UNIT_INTERVAL", " 5 ");
+        let config = DurationUnitConfig::load("duration_unit").expect("Config should parse correctly");
+        assert_eq!(config.timeout, std::time::Duration::from_millis(500));
+        assert_eq!(config.interval, Some(std::time::Duration::from_secs(300)));
+
+        // An explicit unit overrides the field's
+        std::env::set_var("DURATION_UNIT_TIMEOUT", "2s");
+        std::env::remove_var("DURATION_UNIT_INTERVAL");
+        let config = DurationUnitConfig::load("duration_unit").expect("Config should parse correctly");
+        assert_eq!(config.timeout, std::time::Duration::from_secs(2));
+        assert_eq!(config.interval, None);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PresetConfig {
+        #[utils(presets = "none=0s, short=1s, long=30s")]
+        delay: std::time::Duration
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_presets() {
+        // Presets are matched before the value is parsed as a duration
+        for (value, delay) in [("short", 1000), ("LONG", 30_000), ("none", 0), ("250ms", 250)] {
+            std::env::set_var("PRESET_DELAY", value);
+            assert_eq!(PresetConfig::load("preset").expect("Config should parse correctly").delay, std::time::Duration::from_millis(delay));
+        }
+
+        std::env::set_var("PRESET_DELAY", "medium");
+        assert!(PresetConfig::load("preset").is_err());
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct DefaultExprConfig {
+        #[utils(default_expr = std::thread::available_parallelism().unwrap())]
+        workers: NonZeroUsize
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_default_expr() {
+        std::env::remove_var("DEFAULT_EXPR_WORKERS");
+        let config = DefaultExprConfig::load("default_expr").expect("Config should parse correctly");
+        assert_eq!(config.workers, std::thread::available_parallelism().unwrap());
+
+        std::env::set_var("DEFAULT_EXPR_WORKERS", "3");
+        assert_eq!(DefaultExprConfig::load("default_expr").expect("Config should parse correctly").workers.get(), 3);
+
+        std::env::set_var("DEFAULT_EXPR_WORKERS", "0");
+        assert!(matches!(DefaultExprConfig::load("default_expr"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct FileDefaultConfig {
+        #[utils(var_or_file, default = "guest")]
+        user: String
+    }
+
+    // The var wins over its _FILE var, either wins over the default, and a _FILE var that can't be
+    // read is an error rather than a reason to fall back
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_var_or_file_default() {
+        let path = std::env::temp_dir().join("utils_file_default_user.txt");
+        std::fs::write(&path, "from-file").unwrap();
+
+        std::env::set_var("FILE_DEFAULT_USER", "from-var");
+        std::env::set_var("FILE_DEFAULT_USER_FILE", &path);
+        assert_eq!(FileDefaultConfig::load("file_default").expect("Config should parse correctly").user, "from-var");
+
+        std::env::remove_var("FILE_DEFAULT_USER");
+        assert_eq!(FileDefaultConfig::load("file_default").expect("Config should parse correctly").user, "from-file");
+
+        std::env::remove_var("FILE_DEFAULT_USER_FILE");
+        assert_eq!(FileDefaultConfig::load("file_default").expect("Config should parse correctly").user, "guest");
+
+        std::env::set_var("FILE_DEFAULT_USER_FILE", std::env::temp_dir().join("utils_file_default_missing.txt"));
+        let err = FileDefaultConfig::load("file_default").expect_err("Unreadable file should error");
+        assert_eq!(err.var, "FILE_DEFAULT_USER_FILE");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+
+        std::env::remove_var("FILE_DEFAULT_USER_FILE");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    enum Region {
+        EuWest,
+        UsEast,
+        #[utils(other)]
+        Custom(String)
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_other_variant() {
+        assert_eq!(Region::from_env("euwest").ok(), Some(Region::EuWest));
+        assert_eq!(Region::from_env("UsEast").ok(), Some(Region::UsEast));
+        assert_eq!(Region::from_env(" ap-south-2 ").ok(), Some(Region::Custom(String::from("ap-south-2"))));
+    }
+
+    #[test]
+    fn test_prefix_normalization() {
+        assert_eq!(__join_idents("my-app", "host"), "MY_APP_HOST");
+        assert_eq!(__join_idents("my.app", "host"), "MY_APP_HOST");
+        assert_eq!(__join_idents("", "guest_id"), "GUEST_ID");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let list = Lenient::<Vec<u16>>::from_env("80, 443,http,8080,").expect("Lenient lists should always parse");
+        assert_eq!(*list, vec![80, 443, 8080]);
+        assert_eq!(list.skipped.len(), 1);
+        assert_eq!(list.skipped[0].index, 2);
+        assert_eq!(list.skipped[0].value, "http");
+    }
+
+    env_config! {
+        #[derive(Debug)]
+        struct MacroNested {
+            host: String
+        }
+    }
+
+    env_config! {
+        #[derive(Debug)]
+        struct MacroConfig {
+            port: u16 = "MACRO_PORT" default 8080,
+            label: String = _ default String::from("unnamed"),
+            guest_id: Option<u64>,
+            nested: MacroNested
+        }
+    }
+
+    #[test]
+    fn test_env_config_macro() {
+        std::env::remove_var("MACRO_PORT");
+        std::env::set_var("MACRO_NESTED_HOST", "localhost");
+        std::env::set_var("MACRO_LABEL", "primary");
+
+        let config = MacroConfig::load("macro").expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.label, "primary");
+        assert_eq!(config.guest_id, None);
+        assert_eq!(config.nested.host, "localhost");
+    }
+
+    env_config! {
+        #[derive(Debug)]
+        struct MacroBackup {
+            backup: MacroNested = _ default MacroNested { host: String::from("fallback") }
+        }
+    }
+
+    #[test]
+    fn test_env_config_nested_default() {
+        std::env::remove_var("MACRO_BACKUP_BACKUP_HOST");
+        let err = MacroBackup::load("macro_backup").expect_err("Missing nested var should error");
+        assert_eq!(err.var, "MACRO_BACKUP_BACKUP_HOST");
+        assert!(matches!(err.ty, EnvErrorType::NotPresent));
+
+        std::env::set_var("MACRO_BACKUP_BACKUP_HOST", "db");
+        assert_eq!(MacroBackup::load("macro_backup").expect("Config should parse correctly").backup.host, "db");
+    }
+
+    #[cfg(feature = "indexmap")]
+    #[test]
+    fn test_indexmap_order() {
+        let map = indexmap::IndexMap::<String, u32>::from_env("pool=5, timeout=30,retries=2").expect("Map should parse correctly");
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["pool", "timeout", "retries"]);
+        assert_eq!(map["timeout"], 30);
+        assert!(indexmap::IndexMap::<String, u32>::from_env("pool").is_err());
+    }
+
+    #[test]
+    fn test_type_name_in_error() {
+        std::env::set_var("TYPE_NAME_PORT", "eighty");
+        let err = u32::load("TYPE_NAME_PORT").expect_err("Value should not parse");
+        assert_eq!(err.to_string(), "Error parsing environment variable 'TYPE_NAME_PORT': Unable to parse as u32: invalid digit found in string");
+
+        let err = Option::<Masked<u16>>::load("TYPE_NAME_PORT").expect_err("Value should not parse");
+        assert_eq!(err.expected, Some("u16"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PemConfig {
+        #[utils(var_or_file)]
+        pem_cert: String,
+        #[utils(raw_file)]
+        pem_raw: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_multiline_file() {
+        let pem = "-----BEGIN CERTIFICATE-----\nMIIBszCCAVmgAwIBAgIU\n  indented line  \n-----END CERTIFICATE-----\n";
+        let path = std::env::temp_dir().join("utils_test_multiline.pem");
+        std::fs::write(&path, pem).unwrap();
+
+        std::env::remove_var("PEM_CERT");
+        std::env::remove_var("PEM_RAW");
+        std::env::set_var("PEM_CERT_FILE", &path);
+        std::env::set_var("PEM_RAW_FILE", &path);
+
+        let config = PemConfig::load("").expect("Config should parse correctly");
+        assert_eq!(config.pem_cert, pem.strip_suffix('\n').unwrap());
+        assert_eq!(config.pem_raw, pem);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[non_exhaustive]
+    struct GenericConfig<T> {
+        inner: T,
+        fallback: Option<T>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_generic() {
+        std::env::set_var("GENERIC_INNER", "12");
+        std::env::remove_var("GENERIC_FALLBACK");
+
+        let config = GenericConfig::<u16>::load("generic").expect("Config should parse correctly");
+        assert_eq!(config.inner, 12);
+        assert_eq!(config.fallback, None);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(diff)]
+    struct DiffNested {
+        host: String,
+        password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(diff)]
+    struct DiffConfig {
+        port: u16,
+        guest_id: Option<u64>,
+        nested: DiffNested,
+        bind: Option<SocketAddr>,
+        peers: Option<Vec<IpAddr>>,
+        limits: Option<HashMap<String, u16>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_diff() {
+        std::env::set_var("DIFF_PORT", "80");
+        std::env::set_var("DIFF_NESTED_HOST", "a.com");
+        std::env::set_var("DIFF_NESTED_PASSWORD", "hunter2");
+        std::env::remove_var("DIFF_GUEST_ID");
+        let before = DiffConfig::load("diff").expect("Config should parse correctly");
+
+        std::env::set_var("DIFF_NESTED_HOST", "b.com");
+        std::env::set_var("DIFF_NESTED_PASSWORD", "hunter3");
+        let after = DiffConfig::load("diff").expect("Config should parse correctly");
+
+        assert_eq!(before.diff(&after), vec!["nested.host", "nested.password"]);
+        assert!(before.diff(&before).is_empty());
+
+        std::env::set_var("DIFF_BIND", "0.0.0.0:80");
+        std::env::set_var("DIFF_PEERS", "10.0.0.1,10.0.0.2");
+        std::env::set_var("DIFF_LIMITS", "pool=5,idle=2");
+        let before = DiffConfig::load("diff").expect("Config should parse correctly");
+
+        std::env::set_var("DIFF_BIND", "0.0.0.0:81");
+        std::env::set_var("DIFF_PEERS", "10.0.0.1,10.0.0.3");
+        std::env::set_var("DIFF_LIMITS", "pool=6,timeout=1");
+        let after = DiffConfig::load("diff").expect("Config should parse correctly");
+        assert_eq!(before.diff(&after), vec!["bind", "peers.1", "limits.idle", "limits.pool", "limits.timeout"]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct HexConfig {
+        #[utils(hex)]
+        key: Masked<[u8; 4]>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_hex() {
+        std::env::set_var("HEX_KEY", "deadBEEF");
+        let config = HexConfig::load("hex").expect("Config should parse correctly");
+        assert_eq!(config.key.0, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(format!("{:?}", config.key), "***");
+
+        std::env::set_var("HEX_KEY", "deadbe");
+        assert!(matches!(HexConfig::load("hex"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+
+        std::env::set_var("HEX_KEY", "deadbexx");
+        assert!(matches!(HexConfig::load("hex"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct IndirectConfig {
+        // ACTIVE_DB is read as-is, its value then takes the place of the prefix
+        #[utils(name_from = "INDIRECT_ACTIVE_DB")]
+        url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_name_from() {
+        std::env::remove_var("INDIRECT_ACTIVE_DB");
+        let err = IndirectConfig::load("ignored").expect_err("Indirection var is missing");
+        assert_eq!(err.var, "INDIRECT_ACTIVE_DB");
+
+        std::env::set_var("INDIRECT_ACTIVE_DB", "primary");
+        std::env::set_var("PRIMARY_URL", "postgres://primary");
+        std::env::set_var("SECONDARY_URL", "postgres://secondary");
+        assert_eq!(IndirectConfig::load("ignored").expect("Config should parse correctly").url, "postgres://primary");
+
+        std::env::set_var("INDIRECT_ACTIVE_DB", "secondary");
+        assert_eq!(IndirectConfig::load("ignored").expect("Config should parse correctly").url, "postgres://secondary");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct GatedConfig {
+        bucket: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_if_enabled() {
+        std::env::remove_var("GATED_BUCKET");
+        std::env::remove_var("GATED_ENABLED");
+        assert!(GatedConfig::load_if_enabled("GATED_ENABLED", "gated").expect("Unset gate skips loading").is_none());
+
+        std::env::set_var("GATED_ENABLED", "false");
+        assert!(GatedConfig::load_if_enabled("GATED_ENABLED", "gated").expect("False gate skips loading").is_none());
+
+        std::env::set_var("GATED_ENABLED", "true");
+        assert!(GatedConfig::load_if_enabled("GATED_ENABLED", "gated").is_err());
+
+        std::env::set_var("GATED_BUCKET", "logs");
+        let config = GatedConfig::load_if_enabled("GATED_ENABLED", "gated").expect("Config should parse correctly");
+        assert_eq!(config.expect("Gate is enabled").bucket, "logs");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct FlagConfig {
+        enabled: HashSet<String>,
+        #[utils(flag_set, universe = "alpha,beta,gamma")]
+        features: HashMap<String, bool>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_flag_set() {
+        std::env::set_var("FLAGS_ENABLED", "a, b,c,,a");
+        std::env::set_var("FLAGS_FEATURES", "alpha,gamma");
+
+        let config = FlagConfig::load("flags").expect("Config should parse correctly");
+        assert_eq!(config.enabled, HashSet::from([String::from("a"), String::from("b"), String::from("c")]));
+        assert_eq!(config.features, HashMap::from([(String::from("alpha"), true), (String::from("beta"), false), (String::from("gamma"), true)]));
+
+        std::env::set_var("FLAGS_FEATURES", "alpha,delta");
+        assert!(FlagConfig::load("flags").is_err());
+    }
+
+    #[test]
+    fn test_socket_addr_family() {
+        assert_eq!(SocketAddrV4::from_env("127.0.0.1:8080").expect("IPv4 address should parse").port(), 8080);
+        assert!(matches!(SocketAddrV4::from_env("[::1]:8080"), Err(EnvErrorType::Parse(_))));
+        assert!(SocketAddrV6::from_env("[::1]:8080").is_ok());
+        assert!(matches!(SocketAddrV6::from_env("127.0.0.1:8080"), Err(EnvErrorType::Parse(ref message)) if message == "invalid IPv6 socket address syntax"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ShardConfig {
+        host: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ShardedConfig {
+        #[utils(indexed)]
+        shard: [ShardConfig; 3]
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_indexed() {
+        std::env::set_var("SHARDED_SHARD_0_HOST", "a");
+        std::env::set_var("SHARDED_SHARD_1_HOST", "b");
+        std::env::remove_var("SHARDED_SHARD_2_HOST");
+
+        let err = ShardedConfig::load("sharded").expect_err("Missing shard should fail");
+        assert_eq!(err.var, "SHARDED_SHARD_2_HOST");
+
+        std::env::set_var("SHARDED_SHARD_2_HOST", "c");
+        let config = ShardedConfig::load("sharded").expect("Config should parse correctly");
+        assert_eq!(config.shard.map(|shard| shard.host), ["a", "b", "c"]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(finalize = finalize_urls)]
+    struct FinalizeConfig {
+        host: String,
+        public_host: Option<String>
+    }
+
+    #[cfg(feature = "derive")]
+    fn finalize_urls(config: &mut FinalizeConfig) -> Result<(), EnvErrorType> {
+        if config.host.is_empty() {
+            return Err(EnvErrorType::Other(String::from("Host must not be empty")));
+        }
+
+        config.public_host.get_or_insert_with(|| config.host.clone());
+        Ok(())
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_finalize() {
+        std::env::set_var("FINALIZE_HOST", "internal.local");
+        std::env::remove_var("FINALIZE_PUBLIC_HOST");
+        let config = FinalizeConfig::load("finalize").expect("Config should parse correctly");
+        assert_eq!(config.public_host.as_deref(), Some("internal.local"));
+
+        std::env::set_var("FINALIZE_HOST", "");
+        let err = FinalizeConfig::load("finalize").expect_err("Finalize should reject an empty host");
+        assert_eq!(err.to_string(), "Error parsing environment variable 'finalize': Host must not be empty");
+
+        let source = HashMap::from([(String::from("HOST"), String::new())]);
+        let err = FinalizeConfig::load_from("", &source).expect_err("Finalize should reject an empty host");
+        assert_eq!(err.var, "HOST, PUBLIC_HOST");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct AuditNested {
+        host: String,
+        password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct AuditConfig {
+        port: u16,
+        guest_id: Option<u64>,
+        #[utils(var_or_file)]
+        token: String,
+        nested: AuditNested
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_audit() {
+        std::env::set_var("AUDIT_PORT", "eighty");
+        std::env::remove_var("AUDIT_GUEST_ID");
+        std::env::remove_var("AUDIT_TOKEN");
+        std::env::remove_var("AUDIT_TOKEN_FILE");
+        std::env::remove_var("AUDIT_NESTED_HOST");
+        std::env::set_var("AUDIT_NESTED_PASSWORD", "hunter2");
+
+        let report = AuditConfig::audit("audit");
+        assert!(!report.is_ok());
+
+        let statuses: Vec<(&str, &AuditStatus)> = report.entries.iter().map(|entry| (entry.var.as_str(), &entry.status)).collect();
+        assert!(matches!(statuses[..], [
+            ("AUDIT_PORT", AuditStatus::Malformed(_)),
+            ("AUDIT_GUEST_ID", AuditStatus::Unset),
+            ("AUDIT_TOKEN", AuditStatus::Missing),
+            ("AUDIT_NESTED_HOST", AuditStatus::Missing),
+            ("AUDIT_NESTED_PASSWORD", AuditStatus::Present)
+        ]));
+        assert!(!format!("{:?}", report).contains("hunter2"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct AuditSecrets {
+        pin: Masked<u16>,
+        credentials: SecretCredentials
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_audit_redacts_secrets() {
+        std::env::set_var("AUDIT_SECRETS_PIN", "hunter2");
+        std::env::set_var("AUDIT_SECRETS_CREDENTIALS_USER", "admin");
+        std::env::set_var("AUDIT_SECRETS_CREDENTIALS_KEY_ID", "swordfish");
+
+        set_verbose_errors(true);
+        let report = AuditSecrets::audit("audit_secrets");
+        let debug = format!("{:?}", report);
+        set_verbose_errors(false);
+
+        assert!(matches!(report.entries[0].status, AuditStatus::Malformed(_)));
+        assert!(matches!(report.entries[2].status, AuditStatus::Malformed(_)));
+        assert!(!debug.contains("hunter2"));
+        assert!(!debug.contains("swordfish"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct TrimConfig {
+        #[utils(trim_chars = "<> ")]
+        url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_trim_chars() {
+        std::env::set_var("TRIM_URL", " <https://example.com/a<b>> ");
+        let config = TrimConfig::load("trim").expect("Config should parse correctly");
+        assert_eq!(config.url, "https://example.com/a<b");
+    }
+
+    #[cfg(feature = "jiff")]
+    #[test]
+    fn test_jiff() {
+        let timestamp = jiff::Timestamp::from_env("2024-06-01T12:30:00Z").expect("Timestamp should parse");
+        assert_eq!(timestamp.as_second(), 1717245000);
+        assert!(matches!(jiff::Timestamp::from_env("yesterday"), Err(EnvErrorType::InvalidFormat)));
+        assert!(jiff::Zoned::from_env("2024-06-01T12:30:00+02:00[Europe/Paris]").is_ok());
+        assert!(jiff::Span::from_env("P1DT2H").is_ok());
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct OrderConfig {
+        #[utils(after = "second")]
+        first: Masked<String>,
+        #[utils(after = "third")]
+        second: Masked<String>,
+        third: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_order() {
+        std::env::remove_var("ORDER_FIRST");
+        std::env::remove_var("ORDER_SECOND");
+        std::env::remove_var("ORDER_THIRD");
+
+        // The first failure reveals which field was loaded first
+        assert_eq!(OrderConfig::load("order").expect_err("Nothing is set").var, "ORDER_THIRD");
+        std::env::set_var("ORDER_THIRD", "3");
+        assert_eq!(OrderConfig::load("order").expect_err("Only third is set").var, "ORDER_SECOND");
+        std::env::set_var("ORDER_SECOND", "2");
+        std::env::set_var("ORDER_FIRST", "1");
+
+        let config = OrderConfig::load("order").expect("Config should parse correctly");
+        assert_eq!((config.first.0, config.second.0, config.third.0), (String::from("1"), String::from("2"), String::from("3")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PercentConfig {
+        #[utils(percent)]
+        sample_rate: f64,
+        ratio: f32
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_percent() {
+        std::env::set_var("PERCENT_SAMPLE_RATE", "25%");
+        std::env::set_var("PERCENT_RATIO", "0.5");
+        let config = PercentConfig::load("percent").expect("Config should parse correctly");
+        assert_eq!(config.sample_rate, 0.25);
+        assert_eq!(config.ratio, 0.5);
+
+        std::env::set_var("PERCENT_SAMPLE_RATE", "0.25");
+        assert_eq!(PercentConfig::load("percent").expect("Config should parse correctly").sample_rate, 0.25);
+
+        std::env::set_var("PERCENT_RATIO", "50%");
+        assert!(matches!(PercentConfig::load("percent"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(redact)]
+    struct RedactNested {
+        host: String,
+        password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(redact)]
+    struct RedactConfig {
+        port: u16,
+        guest_id: Option<u64>,
+        nested: RedactNested
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(redact)]
+    struct RedactTypesConfig {
+        timeout: Duration,
+        jitter: JitterDuration,
+        data_dir: PathBuf,
+        tokens: Vec<Masked<String>>,
+        limits: HashMap<String, u16>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_redacted_map() {
+        std::env::set_var("REDACT_PORT", "8080");
+        std::env::remove_var("REDACT_GUEST_ID");
+        std::env::set_var("REDACT_NESTED_HOST", "db.local");
+        std::env::set_var("REDACT_NESTED_PASSWORD", "hunter2");
+
+        let config = RedactConfig::load("redact").expect("Config should parse correctly");
+        assert_eq!(config.to_redacted_map("redact"), HashMap::from([
+            (String::from("REDACT_PORT"), String::from("8080")),
+            (String::from("REDACT_NESTED_HOST"), String::from("db.local")),
+            (String::from("REDACT_NESTED_PASSWORD"), String::from("***"))
+        ]));
+
+        std::env::set_var("REDACT_TYPES_TIMEOUT", "1500ms");
+        std::env::set_var("REDACT_TYPES_JITTER", "1s..3s");
+        std::env::set_var("REDACT_TYPES_DATA_DIR", "/var/lib/app");
+        std::env::set_var("REDACT_TYPES_TOKENS", "a,b");
+        std::env::set_var("REDACT_TYPES_LIMITS", "pool=5");
+        let config = RedactTypesConfig::load("redact_types").expect("Config should parse correctly");
+        assert_eq!(config.to_redacted_map("redact_types"), HashMap::from([
+            (String::from("REDACT_TYPES_TIMEOUT"), String::from("1500ms")),
+            (String::from("REDACT_TYPES_JITTER"), String::from("1s..3s")),
+            (String::from("REDACT_TYPES_DATA_DIR"), String::from("/var/lib/app")),
+            (String::from("REDACT_TYPES_TOKENS_0"), String::from("***")),
+            (String::from("REDACT_TYPES_TOKENS_1"), String::from("***")),
+            (String::from("REDACT_TYPES_LIMITS_POOL"), String::from("5"))
+        ]));
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct JsonServer {
+        host: String,
+        port: u16
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    struct JsonConfig {
+        #[utils(json)]
+        servers: Vec<JsonServer>,
+        #[utils(json)]
+        backup: Option<JsonServer>
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_json() {
+        std::env::set_var("JSON_SERVERS", r#"[{"host":"a","port":1},{"host":"b","port":2}]"#);
+        std::env::remove_var("JSON_BACKUP");
+        let config = JsonConfig::load("json").expect("Config should parse correctly");
+        assert_eq!(config.backup, None);
+        assert_eq!(config.servers, vec![
+            JsonServer { host: String::from("a"), port: 1 },
+            JsonServer { host: String::from("b"), port: 2 }
+        ]);
+
+        std::env::set_var("JSON_BACKUP", r#"{"host":"c","port":3}"#);
+        let config = JsonConfig::load("json").expect("Config should parse correctly");
+        assert_eq!(config.backup, Some(JsonServer { host: String::from("c"), port: 3 }));
+
+        std::env::set_var("JSON_SERVERS", r#"[{"host":"a"}]"#);
+        assert!(matches!(JsonConfig::load("json"), Err(EnvError { ty: EnvErrorType::Other(_), .. })));
+    }
+
+    #[test]
+    fn test_percent_type() {
+        assert_eq!(Percent::from_env("25%").ok(), Some(Percent(0.25)));
+        assert_eq!(Percent::from_env("0.25").ok(), Some(Percent(0.25)));
+        assert_eq!(Percent::from_env("0%").ok(), Some(Percent(0.0)));
+        assert_eq!(Percent::from_env("100%").ok(), Some(Percent(1.0)));
+        assert_eq!(Percent::from_env("1").ok(), Some(Percent(1.0)));
+
+        assert!(matches!(Percent::from_env("101%"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(Percent::from_env("-0.1"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(Percent::from_env("abc%"), Err(EnvErrorType::InvalidFormat)));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(builder)]
+    struct BuilderConfig {
+        host: String,
+        port: u16,
+        password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_from_values() {
+        let config = BuilderConfig::from_values("localhost", 8080u16, String::from("hunter2"));
+        assert_eq!(config.host, "localhost");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.password.0, "hunter2");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct LowercaseConfig {
+        #[utils(var_or_file, name = "lower_token")]
+        token: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_lowercase_file_suffix() {
+        let path = std::env::temp_dir().join("utils_lower_token.txt");
+        std::fs::write(&path, "secret\n").unwrap();
+
+        std::env::remove_var("lower_token");
+        std::env::remove_var("LOWER_TOKEN_FILE");
+        std::env::set_var("lower_token_file", &path);
+        let config = LowercaseConfig::load("lower").expect("Config should parse correctly");
+        assert_eq!(config.token, "secret");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(Duration::from_env("30").ok(), Some(Duration::from_secs(30)));
+        assert_eq!(Duration::from_env("250ms").ok(), Some(Duration::from_millis(250)));
+        assert_eq!(Duration::from_env("5m").ok(), Some(Duration::from_secs(300)));
+        assert_eq!(Duration::from_env("2h").ok(), Some(Duration::from_secs(7200)));
+
+        assert_eq!(Duration::from_env("never").ok(), Some(Duration::MAX));
+        assert_eq!(Duration::from_env("Infinite").ok(), Some(Duration::MAX));
+        assert_eq!(Duration::from_env("0").ok(), Some(Duration::ZERO));
+        assert_eq!(Option::<Duration>::from_env("0").ok(), Some(Some(Duration::ZERO)));
+
+        assert_eq!(Duration::from_env("500ms").ok(), Some(Duration::from_millis(500)));
+        assert_eq!(Duration::from_env("45").ok(), Some(Duration::from_secs(45)));
+        assert!(matches!(Duration::from_env("30x"), Err(EnvErrorType::Other(ref message)) if message.contains("ms, s, m, h or d")));
+        assert!(matches!(Duration::from_env("5 parsecs"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(Duration::from_env("soon"), Err(EnvErrorType::Other(ref message)) if message == "Invalid number 'soon'"));
+        assert!(matches!(Duration::from_env("-5s"), Err(EnvErrorType::Other(ref message)) if message == "Durations can't be negative, found '-5s'"));
+    }
+
+    #[test]
+    fn test_duration_errors() {
+        assert!(matches!(Duration::from_env("5x"), Err(EnvErrorType::Other(ref message)) if message == "Unknown duration unit 'x', expected one of ms, s, m, h or d"));
+        assert!(matches!(Duration::from_env("1.2.3s"), Err(EnvErrorType::Other(ref message)) if message == "Invalid number '1.2.3'"));
+        assert!(matches!(Duration::from_env("1.5h"), Err(EnvErrorType::Other(ref message)) if message == "Invalid number '1.5'"));
+
+        let err = EnvError::parse::<Duration>("10 fortnights", "RETRY_DELAY").expect_err("Unknown unit should error");
+        assert_eq!(err.to_string(), "Error parsing environment variable 'RETRY_DELAY': Unknown duration unit 'fortnights', expected one of ms, s, m, h or d");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct TimeoutConfig {
+        #[utils(var_or_file, read_timeout = "100ms")]
+        token: String
+    }
+
+    #[cfg(all(feature = "derive", unix))]
+    #[test]
+    fn test_read_timeout() {
+        let path = std::env::temp_dir().join("utils_timeout_token.txt");
+        std::fs::write(&path, "secret").unwrap();
+
+        std::env::remove_var("TIMEOUT_TOKEN");
+        std::env::set_var("TIMEOUT_TOKEN_FILE", &path);
+        assert_eq!(TimeoutConfig::load("timeout").expect("Config should parse correctly").token, "secret");
+        std::fs::remove_file(&path).unwrap();
+
+        // Opening a fifo blocks until something writes to it, like a hung mount would
+        let fifo = std::env::temp_dir().join("utils_timeout_fifo");
+        let _ = std::fs::remove_file(&fifo);
+        assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+        std::env::set_var("TIMEOUT_TOKEN_FILE", &fifo);
+        let err = TimeoutConfig::load("timeout").expect_err("Read should time out");
+        assert_eq!(err.var, "TIMEOUT_TOKEN_FILE");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+    }
+
+    #[test]
+    fn test_optional_load_or_file() {
+        std::env::remove_var("OPT_FILE_PORT");
+        std::env::remove_var("OPT_FILE_PORT_FILE");
+        assert!(matches!(Option::<u16>::load_or_file("OPT_FILE_PORT"), Ok(None)));
+
+        std::env::set_var("OPT_FILE_PORT", "abc");
+        assert!(matches!(Option::<u16>::load_or_file("OPT_FILE_PORT"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+        std::env::remove_var("OPT_FILE_PORT");
+
+        let path = std::env::temp_dir().join("utils_opt_file_port.txt");
+        std::fs::write(&path, "abc\n").unwrap();
+        std::env::set_var("OPT_FILE_PORT_FILE", &path);
+        assert!(matches!(Option::<u16>::load_or_file("OPT_FILE_PORT"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+        std::fs::remove_file(&path).unwrap();
+
+        let err = Option::<u16>::load_or_file("OPT_FILE_PORT").expect_err("Missing file should error");
+        assert_eq!(err.var, "OPT_FILE_PORT_FILE");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+
+        // A directory exists but can't be read as a file
+        std::env::set_var("OPT_FILE_PORT_FILE", std::env::temp_dir());
+        let err = Option::<u16>::load_or_file("OPT_FILE_PORT").expect_err("Unreadable file should error");
+        assert_eq!(err.var, "OPT_FILE_PORT_FILE");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ConsumeConfig {
+        #[utils(consume)]
+        password: Masked<String>,
+        #[utils(var_or_file, consume)]
+        token: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_consume() {
+        let path = std::env::temp_dir().join("utils_consume_token.txt");
+        std::fs::write(&path, "secret").unwrap();
+
+        std::env::set_var("CONSUME_PASSWORD", "hunter2");
+        std::env::remove_var("CONSUME_TOKEN");
+        std::env::set_var("CONSUME_TOKEN_FILE", &path);
+
+        let config = ConsumeConfig::load("consume").expect("Config should parse correctly");
+        assert_eq!(config.password.0, "hunter2");
+        assert_eq!(config.token.0, "secret");
+        assert!(std::env::var_os("CONSUME_PASSWORD").is_none());
+        assert!(std::env::var_os("CONSUME_TOKEN_FILE").is_none());
+
+        // Under load_from the vars are forgotten by that source, the process environment is left alone
+        std::env::set_var("CONSUME_SOURCE_PASSWORD", "from-env");
+        let source = ForgetfulSource::default();
+        source.0.lock().unwrap().extend([
+            (String::from("CONSUME_SOURCE_PASSWORD"), String::from("hunter2")),
+            (String::from("CONSUME_SOURCE_TOKEN"), String::from("secret"))
+        ]);
+        let config = ConsumeConfig::load_from("consume_source", &source).expect("Config should parse correctly");
+        assert_eq!(config.password.0, "hunter2");
+        assert!(source.0.lock().unwrap().is_empty());
+        assert_eq!(std::env::var("CONSUME_SOURCE_PASSWORD").as_deref(), Ok("from-env"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(Clone, Default)]
+    struct ForgetfulSource(Arc<std::sync::Mutex<HashMap<String, String>>>);
+
+    #[cfg(feature = "derive")]
+    impl EnvSource for ForgetfulSource {
+        fn get(&self, key: &str) -> Result<String, std::env::VarError> {
+            self.0.lock().unwrap().get(key).cloned().ok_or(std::env::VarError::NotPresent)
+        }
+
+        fn forget(&self, key: &str) {
+            self.0.lock().unwrap().remove(key);
+        }
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct MultimapConfig {
+        #[utils(multimap)]
+        headers: HashMap<String, Vec<String>>,
+        #[utils(multimap, pair_sep = "=", entry_sep = ",", value_sep = "|")]
+        routes: HashMap<String, Vec<String>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_multimap() {
+        std::env::set_var("MULTIMAP_HEADERS", "X-Foo:a,b;X-Bar:c");
+        std::env::set_var("MULTIMAP_ROUTES", "api=a|b, web=c");
+
+        let config = MultimapConfig::load("multimap").expect("Config should parse correctly");
+        assert_eq!(config.headers["X-Foo"], ["a", "b"]);
+        assert_eq!(config.headers["X-Bar"], ["c"]);
+        assert_eq!(config.routes["api"], ["a", "b"]);
+        assert_eq!(config.routes["web"], ["c"]);
+
+        std::env::set_var("MULTIMAP_HEADERS", "X-Foo:a;X-Bar");
+        assert!(matches!(MultimapConfig::load("multimap"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ValidateCache {
+        size: u64
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ValidateLimits {
+        memory: u64
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(validate)]
+    struct ValidateConfig {
+        cache: ValidateCache,
+        limits: ValidateLimits
+    }
+
+    #[cfg(feature = "derive")]
+    impl Validate for ValidateConfig {
+        fn validate(&self) -> Result<(), String> {
+            if self.cache.size >= self.limits.memory {
+                return Err(String::from("Cache size must be less than total memory"));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_validate() {
+        std::env::set_var("VALIDATE_CACHE_SIZE", "64");
+        std::env::set_var("VALIDATE_LIMITS_MEMORY", "128");
+        assert!(ValidateConfig::load("validate").is_ok());
+
+        std::env::set_var("VALIDATE_CACHE_SIZE", "256");
+        let err = ValidateConfig::load("validate").expect_err("Invariant should fail");
+        assert_eq!(err.var, "validate");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+
+        let source = HashMap::from([(String::from("CACHE_SIZE"), String::from("256")), (String::from("LIMITS_MEMORY"), String::from("128"))]);
+        let err = ValidateConfig::load_from("", &source).expect_err("Invariant should fail");
+        assert_eq!(err.var, "CACHE_SIZE, LIMITS_MEMORY");
+    }
+
+    #[cfg(all(feature = "derive", feature = "dirs"))]
+    #[derive(FromEnv, Debug)]
+    struct DirConfig {
+        #[utils(default_dir = "utils-test")]
+        data: PathBuf
+    }
+
+    #[cfg(all(feature = "derive", feature = "dirs"))]
+    #[test]
+    fn test_default_dir() {
+        std::env::set_var("DIR_DATA", "/srv/data");
+        assert_eq!(DirConfig::load("dir").expect("Config should parse correctly").data, PathBuf::from("/srv/data"));
+
+        std::env::remove_var("DIR_DATA");
+        if let Some(dir) = dirs::data_dir() {
+            assert_eq!(DirConfig::load("dir").expect("Config should parse correctly").data, dir.join("utils-test"));
+        }
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct EmptyFlagConfig {
+        #[utils(empty_is_true)]
+        verbose: bool,
+        metrics: bool
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_empty_is_true() {
+        std::env::set_var("EMPTY_FLAG_VERBOSE", "");
+        std::env::set_var("EMPTY_FLAG_METRICS", "Enabled");
+        let config = EmptyFlagConfig::load("empty_flag").expect("Config should parse correctly");
+        assert!(config.verbose);
+        assert!(config.metrics);
+
+        std::env::remove_var("EMPTY_FLAG_VERBOSE");
+        std::env::set_var("EMPTY_FLAG_METRICS", "disabled");
+        let config = EmptyFlagConfig::load("empty_flag").expect("Config should parse correctly");
+        assert!(!config.verbose);
+        assert!(!config.metrics);
+
+        // Without the attribute an empty value is still malformed
+        std::env::set_var("EMPTY_FLAG_METRICS", "");
+        assert!(matches!(EmptyFlagConfig::load("empty_flag"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct OverflowConfig {
+        #[utils(overflow = "saturate")]
+        saturated: u8,
+        #[utils(overflow = "wrap")]
+        wrapped: u8,
+        #[utils(overflow = "error")]
+        checked: u8
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_overflow() {
+        std::env::set_var("OVERFLOW_SATURATED", "300");
+        std::env::set_var("OVERFLOW_WRAPPED", "300");
+        std::env::set_var("OVERFLOW_CHECKED", "30");
+        let config = OverflowConfig::load("overflow").expect("Config should parse correctly");
+        assert_eq!(config.saturated, 255);
+        assert_eq!(config.wrapped, 44);
+        assert_eq!(config.checked, 30);
+
+        std::env::set_var("OVERFLOW_SATURATED", "-5");
+        assert_eq!(OverflowConfig::load("overflow").expect("Config should parse correctly").saturated, 0);
+
+        std::env::set_var("OVERFLOW_CHECKED", "300");
+        assert!(matches!(OverflowConfig::load("overflow"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(env_names)]
+    #[allow(dead_code)]
+    struct NamedConfig {
+        port: u16,
+        #[utils(name = "LOG_LEVEL")]
+        level: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_env_names() {
+        assert_eq!(NamedConfig::env_names("named"), [
+            (String::from("port"), String::from("NAMED_PORT")),
+            (String::from("level"), String::from("LOG_LEVEL"))
+        ]);
+    }
+
+    #[cfg(all(feature = "derive", feature = "clap"))]
+    #[test]
+    fn test_clap_env() {
+        std::env::set_var("CLAP_NAMED_PORT", "8080");
+
+        let command = clap::Command::new("app")
+            .arg(clap::Arg::new("port").long("port"))
+            .arg(clap::Arg::new("verbose").long("verbose"));
+        let command = clap_env(command, &NamedConfig::env_names("clap_named"));
+        let matches = command.clone().try_get_matches_from(["app"]).unwrap();
+        assert_eq!(matches.get_one::<String>("port").map(String::as_str), Some("8080"));
+
+        let matches = command.try_get_matches_from(["app", "--port", "9090"]).unwrap();
+        assert_eq!(matches.get_one::<String>("port").map(String::as_str), Some("9090"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct S3Storage {
+        bucket: String,
+        region: Option<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct LocalStorage {
+        path: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    enum Storage {
+        S3(S3Storage),
+        Local(LocalStorage)
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_enum_by_presence() {
+        std::env::remove_var("STORAGE_S3_REGION");
+        std::env::set_var("STORAGE_S3_BUCKET", "assets");
+        std::env::remove_var("STORAGE_LOCAL_PATH");
+        assert!(matches!(Storage::load("storage"), Ok(Storage::S3(S3Storage { ref bucket, .. })) if bucket == "assets"));
+
+        std::env::remove_var("STORAGE_S3_BUCKET");
+        std::env::set_var("STORAGE_LOCAL_PATH", "/srv/assets");
+        assert!(matches!(Storage::load("storage"), Ok(Storage::Local(LocalStorage { ref path })) if path == "/srv/assets"));
+
+        std::env::set_var("STORAGE_S3_BUCKET", "assets");
+        assert!(matches!(Storage::load("storage"), Err(EnvError { ty: EnvErrorType::Other(_), .. })));
+
+        std::env::remove_var("STORAGE_S3_BUCKET");
+        std::env::remove_var("STORAGE_LOCAL_PATH");
+        assert!(matches!(Storage::load("storage"), Err(EnvError { ty: EnvErrorType::NotPresent, .. })));
+        assert!(matches!(Option::<Storage>::load("storage"), Ok(None)));
+
+        std::env::set_var("STORAGE_LOCAL_PATH", "/srv/assets");
+        assert!(matches!(Option::<Storage>::load("storage"), Ok(Some(Storage::Local(_)))));
+        std::env::remove_var("STORAGE_LOCAL_PATH");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct NumberedConfig {
+        #[utils(numbered_from = 1)]
+        origin: Vec<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_numbered_from() {
+        std::env::set_var("NUMBERED_ORIGIN", "a");
+        std::env::set_var("NUMBERED_ORIGIN_2", "b");
+        std::env::set_var("NUMBERED_ORIGIN_3", "c");
+        std::env::remove_var("NUMBERED_ORIGIN_4");
+        std::env::set_var("NUMBERED_ORIGIN_5", "skipped");
+        assert_eq!(NumberedConfig::load("numbered").expect("Config should parse correctly").origin, ["a", "b", "c"]);
+
+        std::env::remove_var("NUMBERED_ORIGIN");
+        assert!(NumberedConfig::load("numbered").expect("Config should parse correctly").origin.is_empty());
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct InternConfig {
+        #[utils(intern)]
+        internal_url: Arc<str>,
+        #[utils(intern)]
+        external_url: Arc<str>,
+        #[utils(intern)]
+        mirrors: Vec<Arc<str>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_intern() {
+        std::env::set_var("INTERN_INTERNAL_URL", "http://example.com");
+        std::env::set_var("INTERN_EXTERNAL_URL", "http://example.com");
+        std::env::set_var("INTERN_MIRRORS", "http://example.com, http://mirror.com, http://mirror.com");
+
+        let mut pool = StringPool::default();
+        let config: InternConfig = pool.load("intern").expect("Config should parse correctly");
+        assert!(Arc::ptr_eq(&config.internal_url, &config.external_url));
+        assert!(Arc::ptr_eq(&config.internal_url, &config.mirrors[0]));
+        assert!(Arc::ptr_eq(&config.mirrors[1], &config.mirrors[2]));
+        assert_eq!(pool.len(), 2);
+
+        // Without a pool, values are only shared within a field
+        let config = InternConfig::load("intern").expect("Config should parse correctly");
+        assert!(!Arc::ptr_eq(&config.internal_url, &config.external_url));
+        assert!(Arc::ptr_eq(&config.mirrors[1], &config.mirrors[2]));
+
+        // A panicking load still hands the pool back
+        struct Exploding;
+        impl FromEnv for Exploding {
+            fn from_env(_: &str) -> Result<Self, EnvErrorType> {
+                panic!("load panicked")
+            }
+        }
+
+        std::env::set_var("INTERN_EXPLODING", "boom");
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| pool.load::<Exploding>("INTERN_EXPLODING")));
+        assert!(res.is_err());
+        assert_eq!(pool.len(), 2);
+        assert!(POOL.with(|pool| pool.borrow().is_none()));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_time() {
+        assert_eq!(time::OffsetDateTime::from_env("2024-05-01T12:30:00Z").ok(), Some(time::macros::datetime!(2024-05-01 12:30 UTC)));
+        assert!(matches!(time::OffsetDateTime::from_env("2024-05-01 12:30"), Err(EnvErrorType::InvalidFormat)));
+
+        assert_eq!(time::Date::from_env("2024-05-01").ok(), Some(time::macros::date!(2024-05-01)));
+        assert!(matches!(time::Date::from_env("2024-13-01"), Err(EnvErrorType::InvalidFormat)));
+
+        assert_eq!(time::Duration::from_env("90s").ok(), Some(time::Duration::seconds(90)));
+        assert!(matches!(time::Duration::from_env("never"), Err(EnvErrorType::InvalidFormat)));
+        assert!(matches!(time::Duration::from_env("soon"), Err(EnvErrorType::Other(_))));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct ComposeConfig {
+        #[utils(compose = "http://{self.host}:{self.port}/api")]
+        internal_url: String,
+        #[utils(compose = "{self.internal_url}?external")]
+        external_url: String,
+        host: String,
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_compose() {
+        std::env::set_var("COMPOSE_HOST", "example.com");
+        std::env::set_var("COMPOSE_PORT", "8080");
+        std::env::remove_var("COMPOSE_INTERNAL_URL");
+        std::env::remove_var("COMPOSE_EXTERNAL_URL");
+
+        let config = ComposeConfig::load("compose").expect("Config should parse correctly");
+        assert_eq!(config.internal_url, "http://example.com:8080/api");
+        assert_eq!(config.external_url, "http://example.com:8080/api?external");
+
+        std::env::set_var("COMPOSE_EXTERNAL_URL", "https://public.example.com");
+        assert_eq!(ComposeConfig::load("compose").expect("Config should parse correctly").external_url, "https://public.example.com");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ReloadLimits {
+        burst: u32
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(reload, validate)]
+    struct ReloadConfig {
+        host: String,
+        port: u16,
+        limits: ReloadLimits,
+        #[utils(compose = "{self.host}:{self.port}")]
+        address: String
+    }
+
+    #[cfg(feature = "derive")]
+    impl Validate for ReloadConfig {
+        fn validate(&self) -> Result<(), String> {
+            if self.port == 0 {
+                return Err(String::from("Port must not be 0"));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_reload_changed() {
+        std::env::set_var("RELOAD_HOST", "a");
+        std::env::set_var("RELOAD_PORT", "1");
+        std::env::set_var("RELOAD_LIMITS_BURST", "10");
+
+        std::env::remove_var("RELOAD_ADDRESS");
+
+        let mut config = ReloadConfig::load("reload").expect("Config should parse correctly");
+        let snapshot = ReloadConfig::snapshot();
+        assert!(config.reload_changed(&snapshot, "reload").expect("Reload should succeed").is_empty());
+
+        // Composed fields follow the fields they're built from
+        std::env::set_var("RELOAD_PORT", "2");
+        std::env::set_var("RELOAD_LIMITS_BURST", "20");
+        assert_eq!(config.reload_changed(&snapshot, "reload").expect("Reload should succeed"), ["port", "limits", "address"]);
+        assert_eq!(config.host, "a");
+        assert_eq!(config.port, 2);
+        assert_eq!(config.limits.burst, 20);
+        assert_eq!(config.address, "a:2");
+
+        // A reload that fails validation leaves the config as it was
+        let snapshot = ReloadConfig::snapshot();
+        std::env::set_var("RELOAD_PORT", "0");
+        std::env::set_var("RELOAD_LIMITS_BURST", "30");
+        assert!(config.reload_changed(&snapshot, "reload").is_err());
+        assert_eq!((config.port, config.limits.burst), (2, 20));
+
+        // Snapshots and comparisons both read the active source
+        let source = HashMap::from([
+            (String::from("RELOAD_SOURCE_HOST"), String::from("b")),
+            (String::from("RELOAD_SOURCE_PORT"), String::from("3")),
+            (String::from("RELOAD_SOURCE_LIMITS_BURST"), String::from("1"))
+        ]);
+        let mut config = ReloadConfig::load_from("reload_source", &source).expect("Config should parse correctly");
+        let snapshot = __with_source(Arc::new(source.clone()), ReloadConfig::snapshot);
+        assert_eq!(snapshot, source);
+        assert!(__with_source(Arc::new(source.clone()), || config.reload_changed(&snapshot, "reload_source")).expect("Reload should succeed").is_empty());
+
+        let mut changed = source.clone();
+        changed.insert(String::from("RELOAD_SOURCE_HOST"), String::from("c"));
+        assert_eq!(__with_source(Arc::new(changed), || config.reload_changed(&snapshot, "reload_source")).expect("Reload should succeed"), ["host", "address"]);
+        assert_eq!(config.address, "c:3");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct MaxBytesConfig {
+        #[utils(max_bytes = 16)]
+        name: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_max_bytes() {
+        std::env::set_var("MAX_BYTES_NAME", "short");
+        assert_eq!(MaxBytesConfig::load("max_bytes").expect("Config should parse correctly").name, "short");
+
+        std::env::set_var("MAX_BYTES_NAME", "x".repeat(17));
+        assert!(matches!(MaxBytesConfig::load("max_bytes"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct DefaultConfig {
+        #[utils(default = "8080")]
+        port: u16,
+        #[utils(default = "true")]
+        verbose: bool,
+        #[utils(var_or_file, default = "guest")]
+        user: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_default() {
+        std::env::set_var("DEFAULT_PORT", "9090");
+        std::env::set_var("DEFAULT_VERBOSE", "false");
+        std::env::set_var("DEFAULT_USER", "admin");
+        let config = DefaultConfig::load("default").expect("Config should parse correctly");
+        assert_eq!(config.port, 9090);
+        assert!(!config.verbose);
+        assert_eq!(config.user, "admin");
+
+        std::env::remove_var("DEFAULT_PORT");
+        std::env::remove_var("DEFAULT_VERBOSE");
+        std::env::remove_var("DEFAULT_USER");
+        std::env::remove_var("DEFAULT_USER_FILE");
+        let config = DefaultConfig::load("default").expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert!(config.verbose);
+        assert_eq!(config.user, "guest");
+
+        // A file that can't be read is an error, not a reason to fall back
+        std::env::set_var("DEFAULT_USER_FILE", "/nonexistent/user.txt");
+        assert!(matches!(DefaultConfig::load("default"), Err(EnvError { ty: EnvErrorType::Other(_), .. })));
+        std::env::remove_var("DEFAULT_USER_FILE");
+
+        std::env::set_var("DEFAULT_PORT", "abc");
+        assert!(matches!(DefaultConfig::load("default"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_case_insensitive() {
+        std::env::set_var("Windows_Case_Port", "8080");
+        assert_eq!(u16::load("WINDOWS_CASE_PORT").ok(), Some(8080));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(tag = "TAGGED_TYPE")]
+    enum TaggedStorage {
+        S3(S3Storage),
+        Local(LocalStorage)
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_enum_by_tag() {
+        std::env::set_var("TAGGED_S3_BUCKET", "assets");
+        std::env::set_var("TAGGED_LOCAL_PATH", "/srv/assets");
+
+        std::env::set_var("TAGGED_TYPE", "s3");
+        assert!(matches!(TaggedStorage::load("tagged"), Ok(TaggedStorage::S3(S3Storage { ref bucket, .. })) if bucket == "assets"));
+
+        std::env::set_var("TAGGED_TYPE", "local");
+        assert!(matches!(TaggedStorage::load("tagged"), Ok(TaggedStorage::Local(LocalStorage { ref path })) if path == "/srv/assets"));
+
+        std::env::set_var("TAGGED_TYPE", "gcs");
+        assert!(matches!(TaggedStorage::load("tagged"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+
+        std::env::remove_var("TAGGED_TYPE");
+        let err = TaggedStorage::load("tagged").expect_err("Missing tag should error");
+        assert_eq!(err.var, "TAGGED_TYPE");
+        assert!(matches!(err.ty, EnvErrorType::NotPresent));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ListConfig {
+        allowed_hosts: Vec<String>,
+        #[utils(delimiter = ";")]
+        ports: Vec<u16>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_vec() {
+        std::env::set_var("LIST_ALLOWED_HOSTS", "a.com, b.com,c.com,");
+        std::env::set_var("LIST_PORTS", "80;443;");
+        let config = ListConfig::load("list").expect("Config should parse correctly");
+        assert_eq!(config.allowed_hosts, ["a.com", "b.com", "c.com"]);
+        assert_eq!(config.ports, [80, 443]);
+
+        std::env::set_var("LIST_ALLOWED_HOSTS", "");
+        assert!(ListConfig::load("list").expect("Config should parse correctly").allowed_hosts.is_empty());
+
+        std::env::set_var("LIST_PORTS", "80;http");
+        let err = ListConfig::load("list").expect_err("Invalid element should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.contains("http")));
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, serde::Deserialize, Debug)]
+    #[utils(blob_var = "BLOB_APP_CONFIG")]
+    struct BlobConfig {
+        host: String,
+        port: u16
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_blob_var() {
+        std::env::remove_var("BLOB_APP_CONFIG");
+        std::env::set_var("BLOB_HOST", "env.local");
+        std::env::set_var("BLOB_PORT", "80");
+        let config = BlobConfig::load("blob").expect("Config should parse correctly");
+        assert_eq!((config.host.as_str(), config.port), ("env.local", 80));
+
+        std::env::set_var("BLOB_APP_CONFIG", r#"{"host":"blob.local","port":8080}"#);
+        let config = BlobConfig::load("blob").expect("Config should parse correctly");
+        assert_eq!((config.host.as_str(), config.port), ("blob.local", 8080));
+
+        std::env::set_var("BLOB_APP_CONFIG", r#"{"host":"blob.local"}"#);
+        assert!(matches!(BlobConfig::load("blob"), Err(EnvError { ty: EnvErrorType::Other(_), .. })));
+    }
+
+    #[test]
+    fn test_hashmap() {
+        let options = HashMap::<String, u32>::from_env("pool=5, timeout=30").expect("Map should parse correctly");
+        assert_eq!(options, HashMap::from([(String::from("pool"), 5), (String::from("timeout"), 30)]));
+
+        let options = HashMap::<String, u32>::from_env("pool=5,pool=10").expect("Map should parse correctly");
+        assert_eq!(options["pool"], 10);
+
+        let options = HashMap::<String, String>::from_env("name=,region=eu").expect("Map should parse correctly");
+        assert_eq!(options["name"], "");
+
+        assert!(matches!(HashMap::<String, u32>::from_env("pool=5,timeout"), Err(EnvErrorType::Other(ref message)) if message.contains("timeout")));
+    }
+
+    #[test]
+    fn test_ipv6_zone() {
+        let addr = SocketAddrV6::from_env("[fe80::1%2]:8080").expect("Zoned address should parse");
+        assert_eq!(addr.scope_id(), 2);
+
+        assert!(matches!(SocketAddrV6::from_env("[fe80::1%eth0]:8080"), Err(EnvErrorType::Other(ref message)) if message.contains("eth0")));
+        assert!(matches!(Ipv6Addr::from_env("fe80::1%2"), Err(EnvErrorType::Other(_))));
+        assert!(Ipv6Addr::from_env("fe80::1").is_ok());
+        assert!(matches!(Ipv6Addr::from_env("fe80::zz"), Err(EnvErrorType::Parse(ref message)) if message == "invalid IPv6 address syntax"));
+        assert!(matches!(IpAddr::from_env("fe80::1%2"), Err(EnvErrorType::Other(ref message)) if message.contains("SocketAddrV6")));
+        assert!(matches!(IpAddr::from_env("fe80::zz"), Err(EnvErrorType::Parse(_))));
+    }
+
+    #[cfg(feature = "derive")]
+    fn parse_pipes(value: &str) -> Result<Vec<String>, EnvErrorType> {
+        if value.is_empty() {
+            return Err(EnvErrorType::Other(String::from("Expected at least one name")));
+        }
+
+        Ok(value.split('|').map(String::from).collect())
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ParseWithConfig {
+        #[utils(parse_with = "parse_pipes")]
+        names: Vec<String>,
+        #[utils(parse_with = parse_pipes, var_or_file, name = "PARSE_WITH_OTHER")]
+        others: Vec<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_parse_with() {
+        std::env::set_var("PARSE_WITH_NAMES", "a|b|c");
+        std::env::set_var("PARSE_WITH_OTHER", "d|e");
+        let config = ParseWithConfig::load("parse_with").expect("Config should parse correctly");
+        assert_eq!(config.names, ["a", "b", "c"]);
+        assert_eq!(config.others, ["d", "e"]);
+
+        std::env::set_var("PARSE_WITH_NAMES", "");
+        let err = ParseWithConfig::load("parse_with").expect_err("Empty value should error");
+        assert_eq!(err.var, "PARSE_WITH_NAMES");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SnapshotServer {
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SnapshotDatabase {
+        url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_env_snapshot() {
+        std::env::set_var("SNAPSHOT_SERVER_PORT", "8080");
+        std::env::set_var("SNAPSHOT_DATABASE_URL", "postgres://db");
+        let snapshot = EnvSnapshot::capture();
+
+        // Later changes don't leak into configs loaded from the snapshot
+        std::env::set_var("SNAPSHOT_SERVER_PORT", "9090");
+        let server: SnapshotServer = snapshot.load("snapshot_server").expect("Config should parse correctly");
+        let database: SnapshotDatabase = snapshot.load("snapshot_database").expect("Config should parse correctly");
+        assert_eq!(server.port, 8080);
+        assert_eq!(database.url, "postgres://db");
+
+        let snapshot = EnvSnapshot::from(HashMap::from([(String::from("SNAPSHOT_SERVER_PORT"), String::from("1"))]));
+        assert_eq!(snapshot.load::<SnapshotServer>("snapshot_server").expect("Config should parse correctly").port, 1);
+        assert!(matches!(snapshot.load::<SnapshotDatabase>("snapshot_database"), Err(EnvError { ty: EnvErrorType::NotPresent, .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PrefixNested {
+        host: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(prefix = "APP")]
+    struct PrefixConfig {
+        id: u32,
+        nested: PrefixNested,
+        #[utils(name = "PREFIX_OVERRIDE")]
+        region: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_struct_prefix() {
+        std::env::set_var("APP_ID", "7");
+        std::env::set_var("APP_NESTED_HOST", "db.local");
+        std::env::set_var("PREFIX_OVERRIDE", "eu");
+
+        let config = PrefixConfig::load("").expect("Config should parse correctly");
+        assert_eq!(config.id, 7);
+        assert_eq!(config.nested.host, "db.local");
+        assert_eq!(config.region, "eu");
+
+        std::env::set_var("APP_STAGING_ID", "8");
+        std::env::set_var("APP_STAGING_NESTED_HOST", "staging.local");
+        let config = PrefixConfig::load("staging").expect("Config should parse correctly");
+        assert_eq!(config.id, 8);
+        assert_eq!(config.nested.host, "staging.local");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct LoadAllNested {
+        host: String,
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct LoadAllConfig {
+        id: u32,
+        nested: LoadAllNested,
+        #[utils(compose = "{self.id}-replica")]
+        replica: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_all() {
+        std::env::set_var("LOAD_ALL_ID", "abc");
+        std::env::remove_var("LOAD_ALL_NESTED_HOST");
+        std::env::remove_var("LOAD_ALL_NESTED_PORT");
+        std::env::remove_var("LOAD_ALL_REPLICA");
+
+        let errors = LoadAllConfig::load_all("load_all").expect_err("Every field should fail");
+        let vars: Vec<&str> = errors.0.iter().map(|err| err.var.as_str()).collect();
+        assert_eq!(vars, ["LOAD_ALL_ID", "LOAD_ALL_NESTED_HOST", "LOAD_ALL_NESTED_PORT"]);
+        assert_eq!(errors.to_string().lines().count(), 3);
+
+        std::env::set_var("LOAD_ALL_ID", "1");
+        std::env::set_var("LOAD_ALL_NESTED_HOST", "db.local");
+        std::env::set_var("LOAD_ALL_NESTED_PORT", "5432");
+        assert_eq!(LoadAllConfig::load_all("load_all").expect("Config should parse correctly").replica, "1-replica");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    enum Feature {
+        Alpha,
+        Beta
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct FeatureConfig {
+        features: Vec<Feature>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_vec_of_enum() {
+        std::env::set_var("FEATURE_FEATURES", "alpha, BETA");
+        assert_eq!(FeatureConfig::load("feature").expect("Config should parse correctly").features, [Feature::Alpha, Feature::Beta]);
+
+        std::env::set_var("FEATURE_FEATURES", "alpha,gamma");
+        let err = FeatureConfig::load("feature").expect_err("Unknown feature should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.contains("gamma")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(redact, explain)]
+    #[allow(dead_code)]
+    struct ExplainConfig {
+        port: u16,
+        password: Masked<String>,
+        nested: RedactNested
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_explain() {
+        std::env::set_var("EXPLAIN_PORT", "8080");
+        std::env::set_var("EXPLAIN_PASSWORD", "hunter2");
+        std::env::set_var("EXPLAIN_NESTED_HOST", "db.local");
+        std::env::set_var("EXPLAIN_NESTED_PASSWORD", "secret");
+
+        let explained = ExplainConfig::explain("explain").expect("Config should parse correctly");
+        assert_eq!(explained, [
+            (String::from("EXPLAIN_PORT"), String::from("8080")),
+            (String::from("EXPLAIN_PASSWORD"), String::from("***")),
+            (String::from("EXPLAIN_NESTED_HOST"), String::from("db.local")),
+            (String::from("EXPLAIN_NESTED_PASSWORD"), String::from("***"))
+        ]);
+
+        std::env::set_var("EXPLAIN_PORT", "abc");
+        assert!(matches!(ExplainConfig::explain("explain"), Err(EnvError { ty: EnvErrorType::Parse(_), .. })));
+    }
+
+    #[test]
+    fn test_net_and_path() {
+        assert_eq!(SocketAddr::from_env("127.0.0.1:8080").ok(), Some(SocketAddr::from(([127, 0, 0, 1], 8080))));
+        assert!(SocketAddr::from_env("[::1]:8080").is_ok());
+        assert!(matches!(SocketAddr::from_env("127.0.0.1"), Err(EnvErrorType::Parse(_))));
+
+        assert_eq!(IpAddr::from_env("10.0.0.1").ok(), Some(IpAddr::from([10, 0, 0, 1])));
+        assert!(matches!(Ipv4Addr::from_env("10.0.0.256"), Err(EnvErrorType::Parse(_))));
+        assert_eq!(PathBuf::from_env("/var/lib/app").ok(), Some(PathBuf::from("/var/lib/app")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[utils(rename_all = "kebab-case")]
+    enum LogFormat {
+        PlainText,
+        #[utils(rename = "json")]
+        JsonLines
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_enum_rename() {
+        assert_eq!(LogFormat::from_env("plain-text").ok(), Some(LogFormat::PlainText));
+        assert_eq!(LogFormat::from_env("JSON").ok(), Some(LogFormat::JsonLines));
+        assert!(matches!(LogFormat::from_env("PlainText"), Err(EnvErrorType::InvalidFormat)));
+        assert_eq!(LogFormat::expected(), "one of 'plain-text', 'json'");
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, serde::Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+    enum SerdeLevel {
+        VeryVerbose,
+        #[serde(rename = "quiet")]
+        Silent,
+        #[serde(rename = "loud")]
+        #[utils(rename = "shout")]
+        Loud
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_enum_serde_rename() {
+        assert_eq!(SerdeLevel::from_env("VERY_VERBOSE").ok(), Some(SerdeLevel::VeryVerbose));
+        assert_eq!(SerdeLevel::from_env("quiet").ok(), Some(SerdeLevel::Silent));
+        assert_eq!(SerdeLevel::from_env("shout").ok(), Some(SerdeLevel::Loud));
+        assert!(matches!(SerdeLevel::from_env("loud"), Err(EnvErrorType::InvalidFormat)));
+        assert_eq!(SerdeLevel::expected(), "one of 'VERY_VERBOSE', 'quiet', 'shout'");
+    }
+
+    #[test]
+    fn test_masked_expose() {
+        let masked = Masked::<String>::from_env("hunter2").expect("Value should parse");
+        assert_eq!(masked.expose(), "hunter2");
+        assert_eq!(format!("{:?}", masked), "***");
+        assert_eq!(masked.into_inner(), "hunter2");
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_masked_zeroize() {
+        use zeroize::Zeroize;
+
+        let mut masked = Masked::<zeroize::Zeroizing<String>>::from_env("hunter2").expect("Value should parse");
+        assert_eq!(masked.expose().as_str(), "hunter2");
+        assert_eq!(format!("{}", masked), "***");
+
+        masked.zeroize();
+        assert!(masked.expose().is_empty());
+
+        fn zeroize_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+        zeroize_on_drop(&masked);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct DotenvConfig {
+        host: String,
+        password: String,
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_dotenv() {
+        let path = std::env::temp_dir().join("utils_test.env");
+        std::fs::write(&path, "# local settings\n\nDOTENV_HOST=localhost\nDOTENV_PASSWORD=\"hunter 2\"\nDOTENV_PORT='8080'\n").unwrap();
+
+        std::env::remove_var("DOTENV_HOST");
+        std::env::remove_var("DOTENV_PASSWORD");
+        std::env::set_var("DOTENV_PORT", "9090");
+
+        let config = DotenvConfig::load_dotenv(path.to_str().unwrap(), "dotenv").expect("Config should parse correctly");
+        assert_eq!(config.host, "localhost");
+        assert_eq!(config.password, "hunter 2");
+        assert_eq!(config.port, 9090);
+        assert!(std::env::var("DOTENV_HOST").is_err());
+
+        // Under another source that source wins over the file and the environment isn't read
+        std::fs::write(&path, "DOTENV_SOURCE_HOST=localhost\nDOTENV_SOURCE_PASSWORD=hunter2\nDOTENV_SOURCE_PORT=8080\n").unwrap();
+        std::env::set_var("DOTENV_SOURCE_PASSWORD", "from-env");
+        let source = HashMap::from([(String::from("DOTENV_SOURCE_PORT"), String::from("7070"))]);
+        let config: DotenvConfig = __with_source(Arc::new(source), || DotenvConfig::load_dotenv(path.to_str().unwrap(), "dotenv_source")).expect("Config should parse correctly");
+        assert_eq!((config.host.as_str(), config.password.as_str(), config.port), ("localhost", "hunter2", 7070));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct EmptyElementConfig {
+        names: Vec<String>,
+        #[utils(skip_empty)]
+        skipped: Vec<String>,
+        ports: Option<Vec<u16>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_vec_empty_elements() {
+        std::env::remove_var("EMPTYELEM_PORTS");
+        for (value, parsed, skipped) in [("A,,B", vec!["A", "", "B"], vec!["A", "B"]), ("A,B,", vec!["A", "B"], vec!["A", "B"]), ("A,,", vec!["A", ""], vec!["A"])] {
+            std::env::set_var("EMPTYELEM_NAMES", value);
+            std::env::set_var("EMPTYELEM_SKIPPED", value);
+            let config = EmptyElementConfig::load("emptyelem").expect("Config should parse correctly");
+            assert_eq!(config.names, parsed);
+            assert_eq!(config.skipped, skipped);
+            assert!(config.ports.is_none());
+        }
+
+        std::env::set_var("EMPTYELEM_PORTS", "80,,443");
+        let err = EmptyElementConfig::load("emptyelem").expect_err("Empty number should error");
+        assert_eq!(err.var, "EMPTYELEM_PORTS");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct ZeroConfig {
+        #[utils(zero_is_none)]
+        idle_timeout: Option<Duration>,
+        #[utils(zero_is_none)]
+        max_retries: Option<u32>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_zero_is_none() {
+        std::env::set_var("ZERO_IDLE_TIMEOUT", "0");
+        std::env::set_var("ZERO_MAX_RETRIES", "0");
+        let config = ZeroConfig::load("zero").expect("Config should parse correctly");
+        assert_eq!(config.idle_timeout, None);
+        assert_eq!(config.max_retries, None);
+
+        std::env::set_var("ZERO_IDLE_TIMEOUT", "0ms");
+        let config = ZeroConfig::load("zero").expect("Config should parse correctly");
+        assert_eq!(config.idle_timeout, None);
+
+        std::env::set_var("ZERO_IDLE_TIMEOUT", "30s");
+        std::env::set_var("ZERO_MAX_RETRIES", "3");
+        let config = ZeroConfig::load("zero").expect("Config should parse correctly");
+        assert_eq!(config.idle_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.max_retries, Some(3));
+    }
+
+    #[test]
+    fn test_bool() {
+        for value in ["true", "1", "yes", "on", "t", "enabled", " TRUE ", "Yes"] {
+            assert_eq!(bool::from_env(value).ok(), Some(true), "{}", value);
+        }
+
+        for value in ["false", "0", "no", "off", "f", "disabled", " OFF "] {
+            assert_eq!(bool::from_env(value).ok(), Some(false), "{}", value);
+        }
+
+        assert!(matches!(bool::from_env("maybe"), Err(EnvErrorType::InvalidFormat)));
+        assert_eq!(bool::expected(), "one of 'true', 'false', '1', '0', 'yes', 'no', 'on', 'off', 't', 'f', 'enabled', 'disabled'");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PluginDatabase {
+        url: String,
+        pool: Option<u32>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(env_names)]
+    struct PluginConfig {
+        enabled: bool,
+        database: PluginDatabase
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_runtime_prefix() {
+        // Plugin names are only known once they're discovered, e.g. from a directory listing
+        let discovered = String::from("cache.redis-v2");
+        let prefix = format!("plugin_{}", discovered);
+
+        std::env::set_var("PLUGIN_CACHE_REDIS_V2_ENABLED", "yes");
+        std::env::set_var("PLUGIN_CACHE_REDIS_V2_DATABASE_URL", "redis://localhost");
+        std::env::remove_var("PLUGIN_CACHE_REDIS_V2_DATABASE_POOL");
+
+        let config = PluginConfig::load(&prefix).expect("Config should parse correctly");
+        assert!(config.enabled);
+        assert_eq!(config.database.url, "redis://localhost");
+        assert_eq!(config.database.pool, None);
+
+        let config = PluginConfig::load_all(&prefix).expect("Config should parse correctly");
+        assert_eq!(config.database.url, "redis://localhost");
+
+        assert_eq!(PluginConfig::env_names(&prefix), [
+            (String::from("enabled"), String::from("PLUGIN_CACHE_REDIS_V2_ENABLED")),
+            (String::from("database.url"), String::from("PLUGIN_CACHE_REDIS_V2_DATABASE_URL")),
+            (String::from("database.pool"), String::from("PLUGIN_CACHE_REDIS_V2_DATABASE_POOL"))
+        ]);
+
+        std::env::remove_var("PLUGIN_CACHE_REDIS_V2_DATABASE_URL");
+        let err = PluginConfig::load(&prefix).expect_err("Missing nested var should error");
+        assert_eq!(err.var, "PLUGIN_CACHE_REDIS_V2_DATABASE_URL");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct AliasConfig {
+        #[utils(alias = "old_db_url", alias = "legacy_db_url")]
+        database_url: String,
+        #[utils(var_or_file, alias = "old_db_password")]
+        database_password: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_alias() {
+        for var in ["ALIAS_DATABASE_URL", "ALIAS_OLD_DB_URL", "ALIAS_LEGACY_DB_URL", "ALIAS_DATABASE_PASSWORD", "ALIAS_DATABASE_PASSWORD_FILE", "ALIAS_OLD_DB_PASSWORD"] {
+            std::env::remove_var(var);
+        }
+
+        let path = std::env::temp_dir().join("utils_alias_password.txt");
+        std::fs::write(&path, "hunter2\n").unwrap();
+        std::env::set_var("ALIAS_OLD_DB_PASSWORD_FILE", &path);
+
+        std::env::set_var("ALIAS_LEGACY_DB_URL", "postgres://legacy");
+        let config = AliasConfig::load("alias").expect("Config should parse correctly");
+        assert_eq!(config.database_url, "postgres://legacy");
+        assert_eq!(config.database_password, "hunter2");
+
+        std::env::set_var("ALIAS_OLD_DB_URL", "postgres://old");
+        assert_eq!(AliasConfig::load("alias").expect("Config should parse correctly").database_url, "postgres://old");
+
+        std::env::set_var("ALIAS_DATABASE_URL", "postgres://new");
+        assert_eq!(AliasConfig::load("alias").expect("Config should parse correctly").database_url, "postgres://new");
+
+        std::env::remove_var("ALIAS_OLD_DB_PASSWORD_FILE");
+        std::fs::remove_file(&path).unwrap();
+        let err = AliasConfig::load("alias").expect_err("Missing field should error");
+        assert_eq!(err.var, "ALIAS_DATABASE_PASSWORD");
+        assert!(matches!(err.ty, EnvErrorType::NotPresent));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct AliasDatabase {
+        #[utils(alias = "dsn")]
+        url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct AliasNestedConfig {
+        database: AliasDatabase,
+        #[utils(alias = "old_db")]
+        replica: AliasDatabase
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_nested_alias() {
+        for var in ["ALIAS_NESTED_DATABASE_URL", "ALIAS_NESTED_REPLICA_URL", "ALIAS_NESTED_REPLICA_DSN", "ALIAS_NESTED_OLD_DB_URL"] {
+            std::env::remove_var(var);
+        }
+
+        // Aliases of nested fields are joined to the nested prefix, aliases of a nested struct
+        // match when any of its vars is set under the alias
+        std::env::set_var("ALIAS_NESTED_DATABASE_DSN", "postgres://primary");
+        std::env::set_var("ALIAS_NESTED_OLD_DB_DSN", "postgres://replica");
+        let config = AliasNestedConfig::load("alias_nested").expect("Config should parse correctly");
+        assert_eq!(config.database.url, "postgres://primary");
+        assert_eq!(config.replica.url, "postgres://replica");
+
+        std::env::set_var("ALIAS_NESTED_REPLICA_URL", "postgres://new");
+        assert_eq!(AliasNestedConfig::load("alias_nested").expect("Config should parse correctly").replica.url, "postgres://new");
+    }
+
+    #[cfg(feature = "tracing-subscriber")]
+    #[test]
+    fn test_log_filter() {
+        let filter = LogFilter::from_env("info,myapp=debug").expect("Filter should parse");
+        assert_eq!(filter.0, "info,myapp=debug");
+        assert_eq!(filter.to_env_filter().to_string(), "myapp=debug,info");
+
+        std::env::set_var("LOG_FILTER_INVALID", "info,myapp=loud");
+        let err = LogFilter::load("LOG_FILTER_INVALID").expect_err("Filter should not parse");
+        assert!(matches!(err.ty, EnvErrorType::Parse(_)));
+        assert!(!err.to_string().contains("myapp=loud"));
+
+        set_verbose_errors(true);
+        assert!(err.to_string().contains("'info,myapp=loud' as LogFilter"));
+        set_verbose_errors(false);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct EnvVarsNested {
+        host: String,
+        #[utils(var_or_file)]
+        password: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct EnvVarsConfig {
+        #[utils(name = "LOG_LEVEL")]
+        log_level: String,
+        database: EnvVarsNested,
+        #[utils(alias = "listen_port")]
+        port: Option<u16>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_env_vars() {
+        assert_eq!(EnvVarsConfig::env_vars("app"), [
+            "LOG_LEVEL",
+            "APP_DATABASE_HOST",
+            "APP_DATABASE_PASSWORD",
+            "APP_DATABASE_PASSWORD_FILE",
+            "APP_PORT",
+            "APP_LISTEN_PORT"
+        ]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct LineConfig {
+        #[utils(line = 1)]
+        host: String,
+        #[utils(line = 2)]
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_line() {
+        std::env::set_var("LINE_HOST", "db.local\n5432");
+        std::env::set_var("LINE_PORT", "db.local\r\n  5432  \n");
+        let config = LineConfig::load("line").expect("Config should parse correctly");
+        assert_eq!(config.host, "db.local");
+        assert_eq!(config.port, 5432);
+
+        std::env::set_var("LINE_PORT", "db.local");
+        let err = LineConfig::load("line").expect_err("Missing line should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.contains("Line 2")));
+    }
+
+    #[test]
+    fn test_char_and_non_zero() {
+        assert_eq!(char::from_env(";").ok(), Some(';'));
+        assert!(matches!(char::from_env(";;"), Err(EnvErrorType::Parse(_))));
+        assert!(matches!(char::from_env(""), Err(EnvErrorType::Parse(_))));
+
+        assert_eq!(NonZeroU32::from_env("8").ok(), NonZeroU32::new(8));
+        assert!(matches!(NonZeroU32::from_env("0"), Err(EnvErrorType::Parse(ref reason)) if reason.contains("zero")));
+        assert!(matches!(NonZeroUsize::from_env("-1"), Err(EnvErrorType::Parse(_))));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(secret, explain)]
+    #[allow(dead_code)]
+    struct SecretCredentials {
+        user: String,
+        key_id: u32
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(redact)]
+    #[allow(dead_code)]
+    struct SecretConfig {
+        host: String,
+        credentials: SecretCredentials
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_secret() {
+        std::env::set_var("SECRET_HOST", "db.local");
+        std::env::set_var("SECRET_CREDENTIALS_USER", "admin");
+        std::env::set_var("SECRET_CREDENTIALS_KEY_ID", "42");
+
+        let config = SecretConfig::load("secret").expect("Config should parse correctly");
+        let map = config.to_redacted_map("secret");
+        assert_eq!(map.len(), 3);
+        assert_eq!(map["SECRET_HOST"], "db.local");
+        assert_eq!(map["SECRET_CREDENTIALS_USER"], "***");
+        assert_eq!(map["SECRET_CREDENTIALS_KEY_ID"], "***");
+
+        let explained = SecretCredentials::explain("secret_credentials").expect("Config should parse correctly");
+        assert_eq!(explained, [
+            (String::from("SECRET_CREDENTIALS_USER"), String::from("***")),
+            (String::from("SECRET_CREDENTIALS_KEY_ID"), String::from("***"))
+        ]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(case_insensitive)]
+    struct CaseConfig {
+        database_url: String,
+        pool: Option<u32>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct CaseSensitiveConfig {
+        database_url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_case_insensitive() {
+        std::env::remove_var("CASE_DATABASE_URL");
+        std::env::set_var("case_database_url", "postgres://lower");
+        std::env::remove_var("CASE_POOL");
+        std::env::set_var("Case_Pool", "4");
+
+        let config = CaseConfig::load("case").expect("Config should parse correctly");
+        assert_eq!(config.database_url, "postgres://lower");
+        assert_eq!(config.pool, Some(4));
+
+        // The exact name wins over other cases
+        std::env::set_var("CASE_DATABASE_URL", "postgres://exact");
+        assert_eq!(CaseConfig::load("case").expect("Config should parse correctly").database_url, "postgres://exact");
+        std::env::remove_var("CASE_DATABASE_URL");
+
+        if !cfg!(windows) {
+            assert!(matches!(CaseSensitiveConfig::load("case"), Err(EnvError { ty: EnvErrorType::NotPresent, .. })));
+        }
+        assert_eq!(CaseSensitiveConfig::load_ci("case").expect("Config should parse correctly").database_url, "postgres://lower");
+    }
+
+    #[test]
+    fn test_case_insensitive_restored_on_panic() {
+        assert!(std::panic::catch_unwind(|| __case_insensitive(|| panic!("load panicked"))).is_err());
+        assert!(!CASE_INSENSITIVE.with(|flag| flag.get()));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    struct BlobDatabase {
+        host: String,
+        port: u16,
+        pool: Option<u32>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[utils(pair_sep = ":", entry_sep = "|")]
+    struct BlobCache {
+        url: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct KeyedConfig {
+        #[utils(pair_sep = ":")]
+        databases: HashMap<String, BlobDatabase>,
+        caches: Vec<BlobCache>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_struct_from_blob() {
+        std::env::set_var("KEYED_DATABASES", "primary:host=a;port=1, replica:host=b;port=2;pool=5");
+        std::env::set_var("KEYED_CACHES", "url:redis://a|, url:redis://b");
+
+        let config = KeyedConfig::load("keyed").expect("Config should parse correctly");
+        assert_eq!(config.databases["primary"], BlobDatabase { host: String::from("a"), port: 1, pool: None });
+        assert_eq!(config.databases["replica"], BlobDatabase { host: String::from("b"), port: 2, pool: Some(5) });
+        assert_eq!(config.caches, [BlobCache { url: String::from("redis://a") }, BlobCache { url: String::from("redis://b") }]);
+
+        std::env::set_var("KEYED_DATABASES", "primary:host=a");
+        let err = KeyedConfig::load("keyed").expect_err("Missing port should error");
+        assert_eq!(err.var, "KEYED_DATABASES");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.contains("PORT")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    struct BlobPool {
+        max_size: u32
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[utils(prefix = "db", separator = "__", rename_all = "camelCase")]
+    struct BlobNamedDatabase {
+        host_name: String,
+        pool: BlobPool
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_struct_from_blob_names() {
+        // Keys follow the container's naming like the fields' vars do
+        let expected = BlobNamedDatabase { host_name: String::from("a"), pool: BlobPool { max_size: 5 } };
+        assert_eq!(BlobNamedDatabase::from_env("hostName=a;pool__max_size=5").ok().as_ref(), Some(&expected));
+        assert_eq!(BlobNamedDatabase::from_env("host_name=a;pool__max_size=5").ok().as_ref(), Some(&expected));
+        assert!(matches!(BlobNamedDatabase::from_env("hostName=a"), Err(EnvErrorType::Other(ref message)) if message.contains("DB__POOL__MAX_SIZE")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(builder)]
+    struct SkipConfig {
+        host: String,
+        #[utils(skip)]
+        cache: Vec<u8>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_skip() {
+        std::env::set_var("SKIP_HOST", "db.local");
+        std::env::set_var("SKIP_CACHE", "1,2,3");
+
+        let config = SkipConfig::load("skip").expect("Config should parse correctly");
+        assert_eq!(config.host, "db.local");
+        assert!(config.cache.is_empty());
+
+        assert!(SkipConfig::load_all("skip").expect("Config should parse correctly").cache.is_empty());
+        assert!(SkipConfig::from_values("db.local").cache.is_empty());
+        assert_eq!(SkipConfig::env_vars("skip"), ["SKIP_HOST"]);
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    #[utils(to_json)]
+    struct JsonDatabase {
+        url: String,
+        password: Masked<String>
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    #[utils(to_json)]
+    struct ToJsonConfig {
+        port: u16,
+        debug: bool,
+        replicas: Option<u8>,
+        database: JsonDatabase,
+        timeout: Option<Duration>,
+        data_dir: Option<PathBuf>,
+        limits: Option<HashMap<String, u16>>
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_to_json() {
+        std::env::set_var("TOJSON_PORT", "8080");
+        std::env::set_var("TOJSON_DEBUG", "on");
+        std::env::remove_var("TOJSON_REPLICAS");
+        std::env::set_var("TOJSON_DATABASE_URL", "postgres://db");
+        std::env::set_var("TOJSON_DATABASE_PASSWORD", "hunter2");
+
+        let config = ToJsonConfig::load("tojson").expect("Config should parse correctly");
+        assert_eq!(config.to_json(), serde_json::json!({
+            "port": 8080,
+            "debug": true,
+            "replicas": null,
+            "database": { "url": "postgres://db", "password": "***" },
+            "timeout": null,
+            "data_dir": null,
+            "limits": null
+        }));
+        assert!(!config.to_json().to_string().contains("hunter2"));
+
+        std::env::set_var("TOJSON_TIMEOUT", "90");
+        std::env::set_var("TOJSON_DATA_DIR", "/var/lib/app");
+        std::env::set_var("TOJSON_LIMITS", "pool=5");
+        let json = ToJsonConfig::load("tojson").expect("Config should parse correctly").to_json();
+        assert_eq!(json["timeout"], "90s");
+        assert_eq!(json["data_dir"], "/var/lib/app");
+        assert_eq!(json["limits"], serde_json::json!({ "pool": 5 }));
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq!(<[u64; 3]>::from_env("100, 200,400").ok(), Some([100, 200, 400]));
+        assert!(matches!(<[u64; 3]>::from_env("100,200"), Err(EnvErrorType::Other(ref message)) if message == "Expected 3 elements, found 2"));
+        assert!(matches!(<[u64; 3]>::from_env("100,200,400,800"), Err(EnvErrorType::Other(ref message)) if message == "Expected 3 elements, found 4"));
+        assert!(matches!(<[u64; 3]>::from_env("100,fast,400"), Err(EnvErrorType::Other(ref message)) if message.contains("fast")));
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct JsonPair {
+        a: u32,
+        b: String
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    struct SerdeJsonConfig {
+        #[utils(serde_json)]
+        inline: JsonPair,
+        #[utils(serde_json, var_or_file)]
+        from_file: JsonPair
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_serde_json() {
+        let path = std::env::temp_dir().join("utils_serde_json.json");
+        std::fs::write(&path, "{\"a\":2,\"b\":\"y\"}\n").unwrap();
+
+        std::env::set_var("SERDEJSON_INLINE", "{\"a\":1,\"b\":\"x\"}");
+        std::env::remove_var("SERDEJSON_FROM_FILE");
+        std::env::set_var("SERDEJSON_FROM_FILE_FILE", &path);
+
+        let config = SerdeJsonConfig::load("serdejson").expect("Config should parse correctly");
+        assert_eq!(config.inline, JsonPair { a: 1, b: String::from("x") });
+        assert_eq!(config.from_file, JsonPair { a: 2, b: String::from("y") });
+
+        std::env::set_var("SERDEJSON_INLINE", "{\"a\":\"one\"}");
+        let err = SerdeJsonConfig::load("serdejson").expect_err("Invalid JSON should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(_)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(deny_unmarked)]
+    struct MarkedConfig {
+        #[utils(required)]
+        host: String,
+        #[utils(default = "8080")]
+        port: u16,
+        replicas: Option<u8>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_deny_unmarked() {
+        std::env::set_var("MARKED_HOST", "db.local");
+        std::env::remove_var("MARKED_PORT");
+        std::env::remove_var("MARKED_REPLICAS");
+
+        let config = MarkedConfig::load("marked").expect("Config should parse correctly");
+        assert_eq!((config.host.as_str(), config.port, config.replicas), ("db.local", 8080, None));
+    }
+
+    #[test]
+    fn test_auto_bool() {
+        assert_eq!(AutoBool::from_env("auto").ok(), Some(AutoBool::Auto));
+        assert_eq!(AutoBool::from_env("Always").ok(), Some(AutoBool::Always));
+        assert_eq!(AutoBool::from_env(" never ").ok(), Some(AutoBool::Never));
+        assert_eq!(AutoBool::from_env("on").ok(), Some(AutoBool::Always));
+        assert_eq!(AutoBool::from_env("OFF").ok(), Some(AutoBool::Never));
+        assert!(matches!(AutoBool::from_env("true"), Err(EnvErrorType::InvalidFormat)));
+
+        assert!(AutoBool::Auto.resolve(true));
+        assert!(!AutoBool::Auto.resolve(false));
+        assert!(AutoBool::Always.resolve(false));
+        assert!(!AutoBool::Never.resolve(true));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    struct Port(u16);
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    struct Replicas(Option<u8>);
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct NewtypeConfig {
+        port: Port,
+        replicas: Replicas
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_newtype() {
+        std::env::set_var("NEWTYPE_PORT", "8080");
+        std::env::remove_var("NEWTYPE_REPLICAS");
+        let config = NewtypeConfig::load("newtype").expect("Config should parse correctly");
+        assert_eq!(config.port, Port(8080));
+        assert_eq!(config.replicas, Replicas(None));
+
+        std::env::set_var("NEWTYPE_PORT", "http");
+        let err = NewtypeConfig::load("newtype").expect_err("Invalid port should error");
+        assert_eq!(err.expected, Some("Port"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(env_names)]
+    struct SeparatorInner {
+        host: String,
+        max_connections: u32
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SeparatorNested {
+        inner: SeparatorInner
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(separator = "__")]
+    struct SeparatorConfig {
+        nested: SeparatorNested,
+        log_level: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_separator() {
+        std::env::set_var("APP__NESTED__INNER__HOST", "db.local");
+        std::env::set_var("APP__NESTED__INNER__MAX_CONNECTIONS", "10");
+        std::env::set_var("APP__LOG_LEVEL", "debug");
+
+        let config = SeparatorConfig::load("app").expect("Config should parse correctly");
+        assert_eq!(config.nested.inner.host, "db.local");
+        assert_eq!(config.nested.inner.max_connections, 10);
+        assert_eq!(config.log_level, "debug");
+        assert_eq!(SeparatorConfig::env_vars("app"), ["APP__NESTED__INNER__HOST", "APP__NESTED__INNER__MAX_CONNECTIONS", "APP__LOG_LEVEL"]);
+
+        // Outside of the struct the default separator is back
+        assert_eq!(SeparatorInner::env_names("app")[0].1, "APP_HOST");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct VerboseConfig {
+        port: u16,
+        pin: Option<Masked<u16>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_verbose_errors() {
+        std::env::set_var("VERBOSE_PORT", "http");
+        std::env::set_var("VERBOSE_PIN", "12ab");
+        let err = VerboseConfig::load("verbose").expect_err("Invalid port should error");
+        assert_eq!(err.value.as_deref(), Some("http"));
+        assert_eq!(err.to_string(), "Error parsing environment variable 'VERBOSE_PORT': Unable to parse as u16: invalid digit found in string");
+
+        set_verbose_errors(true);
+        assert_eq!(err.to_string(), "Error parsing environment variable 'VERBOSE_PORT': Unable to parse 'http' as u16: invalid digit found in string");
+
+        std::env::set_var("VERBOSE_PORT", "80");
+        let err = VerboseConfig::load("verbose").expect_err("Invalid pin should error");
+        assert_eq!(err.value, None);
+        assert!(!err.to_string().contains("12ab"));
+        set_verbose_errors(false);
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    struct AutoJsonConfig {
+        #[utils(auto_json)]
+        hosts: Vec<String>,
+        #[utils(auto_json)]
+        ports: Vec<u16>
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_auto_json() {
+        std::env::set_var("AUTOJSON_HOSTS", "a.com, b.com");
+        std::env::set_var("AUTOJSON_PORTS", "80,443");
+        let list = AutoJsonConfig::load("autojson").expect("Config should parse correctly");
+
+        std::env::set_var("AUTOJSON_HOSTS", " [\"a.com\", \"b.com\"]");
+        std::env::set_var("AUTOJSON_PORTS", "[80, 443]");
+        let json = AutoJsonConfig::load("autojson").expect("Config should parse correctly");
+
+        assert_eq!(list.hosts, ["a.com", "b.com"]);
+        assert_eq!(list.hosts, json.hosts);
+        assert_eq!(list.ports, json.ports);
+
+        std::env::set_var("AUTOJSON_PORTS", "[80, \"https\"]");
+        assert!(matches!(AutoJsonConfig::load("autojson"), Err(EnvError { ty: EnvErrorType::Other(_), .. })));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SourceDatabase {
+        url: String,
+        #[utils(var_or_file)]
+        password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SourceConfig {
+        port: u16,
+        replicas: Option<u8>,
+        database: SourceDatabase
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_from() {
+        let source = HashMap::from([
+            (String::from("SOURCE_PORT"), String::from("8080")),
+            (String::from("SOURCE_DATABASE_URL"), String::from("postgres://db")),
+            (String::from("SOURCE_DATABASE_PASSWORD"), String::from("hunter2"))
+        ]);
+
+        let config = SourceConfig::load_from("source", &source).expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.replicas, None);
+        assert_eq!(config.database.url, "postgres://db");
+        assert_eq!(config.database.password.0, "hunter2");
+        assert!(std::env::var("SOURCE_PORT").is_err());
+
+        let err = SourceConfig::load_from("source", &HashMap::new()).expect_err("Empty source should error");
+        assert_eq!(err.var, "SOURCE_PORT");
+        assert!(matches!(SourceConfig::load_from("source", &StdEnv), Err(EnvError { ty: EnvErrorType::NotPresent, .. })));
+    }
+
+    #[test]
+    fn test_source_restored_on_panic() {
+        let source = HashMap::from([(String::from("SOURCE_PANIC_HOST"), String::from("db"))]);
+        let res = std::panic::catch_unwind(|| __with_source(Arc::new(source), || panic!("load panicked")));
+        assert!(res.is_err());
+        assert!(SOURCE.with(|current| current.borrow().is_none()));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_chained_source() {
+        let env = HashMap::from([
+            (String::from("CHAINED_PORT"), String::from("9090"))
+        ]);
+        let store = HashMap::from([
+            (String::from("CHAINED_PORT"), String::from("8080")),
+            (String::from("CHAINED_DATABASE_URL"), String::from("postgres://db")),
+            (String::from("CHAINED_DATABASE_PASSWORD"), String::from("hunter2"))
+        ]);
+
+        let source = ChainedSource::new().then(env).then(store);
+        let config = SourceConfig::load_from("chained", &source).expect("Config should parse correctly");
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.database.url, "postgres://db");
+        assert_eq!(source.keys(), ["CHAINED_DATABASE_PASSWORD", "CHAINED_DATABASE_URL", "CHAINED_PORT"]);
+        assert!(matches!(source.get("CHAINED_REPLICAS"), Err(std::env::VarError::NotPresent)));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct BoundsConfig {
+        #[utils(min = 1, max = 65535)]
+        port: u32,
+        #[utils(min = 1, default = "4")]
+        workers: u16,
+        #[utils(min = -10, max = 0.5)]
+        offset: Option<f64>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_bounds() {
+        std::env::set_var("BOUNDS_PORT", "8080");
+        std::env::remove_var("BOUNDS_WORKERS");
+        std::env::remove_var("BOUNDS_OFFSET");
+        let config = BoundsConfig::load("bounds").expect("Config should parse correctly");
+        assert_eq!((config.port, config.workers, config.offset), (8080, 4, None));
+
+        std::env::set_var("BOUNDS_PORT", "0");
+        let err = BoundsConfig::load("bounds").expect_err("Port below min should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Must be at least 1"));
+
+        std::env::set_var("BOUNDS_PORT", "70000");
+        let err = BoundsConfig::load("bounds").expect_err("Port above max should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Must be at most 65535"));
+        assert_eq!(BoundsConfig::load_all("bounds").expect_err("Port above max should error").0[0].var, "BOUNDS_PORT");
+
+        std::env::set_var("BOUNDS_PORT", "80");
+        std::env::set_var("BOUNDS_WORKERS", "0");
+        assert!(BoundsConfig::load("bounds").is_err());
+
+        std::env::set_var("BOUNDS_WORKERS", "2");
+        std::env::set_var("BOUNDS_OFFSET", "-10.5");
+        assert!(BoundsConfig::load("bounds").is_err());
+        std::env::set_var("BOUNDS_OFFSET", "-2");
+        assert_eq!(BoundsConfig::load("bounds").expect("Config should parse correctly").offset, Some(-2.0));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct MinLenConfig {
+        #[utils(min_len_chars = 32)]
+        api_key: Masked<String>,
+        #[utils(min_len_chars = 4)]
+        user: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_min_len_chars() {
+        std::env::set_var("MINLEN_API_KEY", "0123456789abcdef0123456789abcdef");
+        std::env::set_var("MINLEN_USER", "admin");
+        let config = MinLenConfig::load("minlen").expect("Config should parse correctly");
+        assert_eq!(config.api_key.0.len(), 32);
+        assert_eq!(config.user, "admin");
+
+        std::env::set_var("MINLEN_USER", "bob");
+        assert!(MinLenConfig::load("minlen").is_err());
+        std::env::set_var("MINLEN_USER", "admin");
+
+        std::env::set_var("MINLEN_API_KEY", "changeme");
+        let err = MinLenConfig::load("minlen").expect_err("Short secret should error");
+        assert_eq!(err.var, "MINLEN_API_KEY");
+        assert_eq!(err.value, None);
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Must be at least 32 characters"));
+        assert!(!err.to_string().contains("changeme"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct AllowlistConfig {
+        #[utils(dedup)]
+        trusted_ips: Vec<IpAddr>,
+        #[utils(dedup, sort)]
+        sorted_ips: Vec<IpAddr>,
+        #[utils(sort)]
+        ports: Vec<u16>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_dedup_and_sort() {
+        std::env::set_var("ALLOWLIST_TRUSTED_IPS", "5.6.7.8,1.2.3.4,5.6.7.8,1.2.3.4");
+        std::env::set_var("ALLOWLIST_SORTED_IPS", "5.6.7.8,1.2.3.4,5.6.7.8");
+        std::env::set_var("ALLOWLIST_PORTS", "443,80,443");
+
+        let config = AllowlistConfig::load("allowlist").expect("Config should parse correctly");
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+        assert_eq!(config.trusted_ips, [ip("5.6.7.8"), ip("1.2.3.4")]);
+        assert_eq!(config.sorted_ips, [ip("1.2.3.4"), ip("5.6.7.8")]);
+        assert_eq!(config.ports, [80, 443, 443]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[allow(dead_code)]
+    #[derive(FromEnv, Debug)]
+    struct HelpInner {
+        name: String,
+        #[utils(var_or_file)]
+        token: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[allow(dead_code)]
+    #[derive(FromEnv, Debug)]
+    struct HelpConfig {
+        app_id: u64,
+        guest_id: Option<u64>,
+        #[utils(default = "8080")]
+        port: u16,
+        test: HelpInner,
+        extra: Option<HelpInner>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_help() {
+        let lines: Vec<String> = HelpConfig::help("").lines().map(String::from).collect();
+        assert_eq!(lines, [
+            "APP_ID (required)",
+            "GUEST_ID (optional)",
+            "PORT (optional)",
+            "TEST_NAME (required)",
+            "TEST_TOKEN (required, or TEST_TOKEN_FILE)",
+            "EXTRA_NAME (optional)",
+            "EXTRA_TOKEN (optional, or EXTRA_TOKEN_FILE)"
+        ]);
+
+        // env_vars lists the same vars, and any of the optional struct's vars being set loads it
+        assert_eq!(HelpConfig::env_vars("help"), [
+            "HELP_APP_ID", "HELP_GUEST_ID", "HELP_PORT", "HELP_TEST_NAME", "HELP_TEST_TOKEN", "HELP_TEST_TOKEN_FILE",
+            "HELP_EXTRA_NAME", "HELP_EXTRA_TOKEN", "HELP_EXTRA_TOKEN_FILE"
+        ]);
+
+        std::env::set_var("HELP_APP_ID", "1");
+        std::env::set_var("HELP_TEST_NAME", "test");
+        std::env::set_var("HELP_TEST_TOKEN", "abc");
+        let config = HelpConfig::load("help").expect("Config should parse correctly");
+        assert!(config.extra.is_none());
+
+        std::env::set_var("HELP_EXTRA_NAME", "extra");
+        let err = HelpConfig::load("help").expect_err("Half set optional struct should error");
+        assert_eq!(err.var, "HELP_EXTRA_TOKEN");
+
+        std::env::set_var("HELP_EXTRA_TOKEN", "def");
+        let config = HelpConfig::load("help").expect("Config should parse correctly");
+        assert_eq!(config.extra.map(|extra| extra.name), Some(String::from("extra")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    struct EmptyConfig;
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct WithEmptyConfig {
+        empty: EmptyConfig,
+        name: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_unit_struct() {
+        assert_eq!(EmptyConfig::load("unit_empty").expect("Unit structs always load"), EmptyConfig);
+        assert!(EmptyConfig::env_vars("unit_empty").is_empty());
+
+        std::env::set_var("UNIT_WITH_NAME", "frontend");
+        let config = WithEmptyConfig::load("unit_with").expect("Config should parse correctly");
+        assert_eq!(config.empty, EmptyConfig);
+        assert_eq!(config.name, "frontend");
+        assert_eq!(WithEmptyConfig::env_vars("unit_with"), ["UNIT_WITH_NAME"]);
+    }
+
+    #[test]
+    fn test_option_empty_is_none() {
+        std::env::set_var("OPTION_EMPTY_GUEST_ID", "");
+        std::env::set_var("OPTION_BLANK_GUEST_ID", "  ");
+        std::env::set_var("OPTION_SET_GUEST_ID", "42");
+
+        assert_eq!(Option::<u64>::load("OPTION_EMPTY_GUEST_ID").expect("Empty should be None"), None);
+        assert_eq!(Option::<u64>::load_or_file("OPTION_BLANK_GUEST_ID").expect("Blank should be None"), None);
+        assert_eq!(Option::<u64>::load("OPTION_SET_GUEST_ID").expect("Value should parse"), Some(42));
+        assert!(matches!(u64::load("OPTION_EMPTY_GUEST_ID").unwrap_err().ty, EnvErrorType::Parse(_)));
+    }
+
+    #[test]
+    fn test_jitter_duration() {
+        let range = JitterDuration::from_env("1s..3s").expect("Range should parse");
+        assert_eq!((range.min, range.max), (Duration::from_secs(1), Duration::from_secs(3)));
+
+        let single = JitterDuration::from_env("500ms").expect("Single value should parse");
+        assert_eq!((single.min, single.max), (Duration::from_millis(500), Duration::from_millis(500)));
+
+        let from_zero = JitterDuration::from_env("0..3s").expect("Range from zero should parse");
+        assert_eq!((from_zero.min, from_zero.max), (Duration::ZERO, Duration::from_secs(3)));
+
+        assert!(matches!(JitterDuration::from_env("3s..1s"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(JitterDuration::from_env("1s..soon"), Err(EnvErrorType::Other(_))));
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_jitter_duration_sample() {
+        let range = JitterDuration::from_env("1s..3s").expect("Range should parse");
+        for _ in 0..100 {
+            let sample = range.sample();
+            assert!(sample >= Duration::from_secs(1) && sample <= Duration::from_secs(3));
+        }
+
+        assert_eq!(JitterDuration::from_env("2s").expect("Single value should parse").sample(), Duration::from_secs(2));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    enum LogLevel {
+        Debug,
+        Info,
+        Warn,
+        Error
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_unit_enum() {
+        std::env::set_var("UNIT_ENUM_LOG_LEVEL", "info");
+        assert_eq!(LogLevel::load("UNIT_ENUM_LOG_LEVEL").expect("Level should parse"), LogLevel::Info);
+
+        std::env::set_var("UNIT_ENUM_LOG_LEVEL", "trace");
+        let err = LogLevel::load("UNIT_ENUM_LOG_LEVEL").expect_err("Unknown level should error");
+        assert!(matches!(err.ty, EnvErrorType::InvalidFormat));
+        assert_eq!(err.expected, Some("one of 'Debug', 'Info', 'Warn', 'Error'"));
+        assert_eq!(err.to_string(), "Error parsing environment variable 'UNIT_ENUM_LOG_LEVEL': Unable to parse as one of 'Debug', 'Info', 'Warn', 'Error'");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[non_exhaustive]
+    enum Compression {
+        Gzip,
+        Zstd
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    enum Limit<T> {
+        Unlimited,
+        #[utils(other)]
+        Fixed(T)
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    enum Backend<T> {
+        Local(LocalStorage),
+        Custom(T)
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_enum_shapes() {
+        assert_eq!(Compression::from_env("zstd").ok(), Some(Compression::Zstd));
+        assert!(Compression::from_env("brotli").is_err());
+
+        assert_eq!(Limit::<u32>::from_env("unlimited").ok(), Some(Limit::Unlimited));
+        assert_eq!(Limit::<u32>::from_env("100").ok(), Some(Limit::Fixed(100)));
+        assert!(matches!(Limit::<u32>::from_env("lots"), Err(EnvErrorType::InvalidFormat)));
+        assert_eq!(<Limit<u32>>::expected(), "one of 'Unlimited'");
+
+        std::env::remove_var("GENERIC_BACKEND_LOCAL_PATH");
+        std::env::set_var("GENERIC_BACKEND_CUSTOM", "8080");
+        assert!(matches!(Backend::<u16>::load("generic_backend"), Ok(Backend::Custom(8080))));
+
+        std::env::remove_var("GENERIC_BACKEND_CUSTOM");
+        std::env::set_var("GENERIC_BACKEND_LOCAL_PATH", "/srv");
+        assert!(matches!(Backend::<u16>::load("generic_backend"), Ok(Backend::Local(LocalStorage { ref path })) if path == "/srv"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct NormalizeConfig {
+        #[utils(trim, lowercase)]
+        region: String,
+        #[utils(trim, lowercase, var_or_file)]
+        region_file: String,
+        #[utils(trim)]
+        token: Masked<String>,
+        #[utils(trim, lowercase)]
+        level: LogLevel,
+        #[utils(trim)]
+        enabled: bool
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_trim_and_lowercase() {
+        let path = std::env::temp_dir().join("utils_normalize_region");
+        std::fs::write(&path, "  EU-West-1 \n").expect("Temp file should be writable");
+
+        std::env::set_var("NORMALIZE_REGION", "  US-East-1\t");
+        std::env::set_var("NORMALIZE_REGION_FILE_FILE", path.to_str().unwrap());
+        std::env::set_var("NORMALIZE_TOKEN", " hunter2 ");
+        std::env::set_var("NORMALIZE_LEVEL", "  WARN ");
+        std::env::set_var("NORMALIZE_ENABLED", " YES ");
+
+        let config = NormalizeConfig::load("normalize").expect("Config should parse correctly");
+        assert_eq!(config.region, "us-east-1");
+        assert_eq!(config.region_file, "eu-west-1");
+        assert_eq!(config.token.expose(), "hunter2");
+        assert_eq!(config.level, LogLevel::Warn);
+        assert!(config.enabled);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct PlatformConfig {
+        name: String,
+        #[cfg(unix)]
+        socket_path: PathBuf,
+        #[cfg(not(unix))]
+        pipe_name: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_cfg_fields() {
+        std::env::set_var("PLATFORM_NAME", "agent");
+        #[cfg(unix)]
+        std::env::set_var("PLATFORM_SOCKET_PATH", "/run/agent.sock");
+        #[cfg(not(unix))]
+        std::env::set_var("PLATFORM_PIPE_NAME", r"\\.\pipe\agent");
+
+        let config = PlatformConfig::load("platform").expect("Config should parse correctly");
+        assert_eq!(config.name, "agent");
+        #[cfg(unix)]
+        assert_eq!(config.socket_path, PathBuf::from("/run/agent.sock"));
+        #[cfg(not(unix))]
+        assert_eq!(config.pipe_name, r"\\.\pipe\agent");
+        assert_eq!(PlatformConfig::env_vars("platform").len(), 2);
+    }
+
+    #[test]
+    fn test_base64_value() {
+        assert_eq!(__base64_value("aGVsbG8=").ok(), Some(String::from("hello")));
+        assert_eq!(__base64_value("aGVsbG8").ok(), Some(String::from("hello")));
+        assert_eq!(__base64_value("").ok(), Some(String::new()));
+        assert_eq!(__base64_value(" aGVs\r\nbG8g\nd29y\tbGQ=\n").ok(), Some(String::from("hello world")));
+        assert!(matches!(__base64_value("aGVsb*8="), Err(EnvErrorType::InvalidFormat)));
+        assert!(matches!(__base64_value("/w=="), Err(EnvErrorType::InvalidFormat)));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct Base64Config {
+        #[utils(base64_value, line = 2)]
+        port: u16,
+        #[utils(base64_value, var_or_file)]
+        motd: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_base64_value_field() {
+        let path = std::env::temp_dir().join("utils_base64_motd");
+        std::fs::write(&path, "d2VsY29tZQpiYWNr\n").expect("Temp file should be writable");
+
+        // "host\n8080\n"
+        std::env::set_var("BASE64_PORT", "aG9zdAo4MDgwCg==");
+        std::env::set_var("BASE64_MOTD_FILE", path.to_str().unwrap());
+
+        let config = Base64Config::load("base64").expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.motd, "welcome\nback");
+
+        std::env::set_var("BASE64_PORT", "not base64!");
+        assert!(matches!(Base64Config::load("base64"), Err(EnvError { ty: EnvErrorType::InvalidFormat, .. })));
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        std::env::set_var("PARSE_ERROR_RATIO", "3.x");
+        let err = f64::load("PARSE_ERROR_RATIO").expect_err("Value should not parse");
+        assert!(matches!(err.ty, EnvErrorType::Parse(ref reason) if reason == "invalid float literal"));
+
+        set_verbose_errors(true);
+        assert_eq!(err.to_string(), "Error parsing environment variable 'PARSE_ERROR_RATIO': Unable to parse '3.x' as f64: invalid float literal");
+
+        let err = EnvError { var: String::from("PARSE_ERROR_NAME"), ty: EnvErrorType::NotUnicode(OsString::from("caf\u{e9}")), expected: None, value: None };
+        assert_eq!(err.to_string(), "Error parsing environment variable 'PARSE_ERROR_NAME': Not valid unicode: 'caf\u{e9}'");
+        assert_eq!(format!("{:?}", err.ty), "NotUnicode(\"caf\u{e9}\")");
+        set_verbose_errors(false);
+        assert_eq!(err.to_string(), "Error parsing environment variable 'PARSE_ERROR_NAME': Not valid unicode");
+        assert_eq!(format!("{:?}", err.ty), "NotUnicode(..)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_masked_not_unicode() {
+        use std::os::unix::ffi::OsStrExt;
+
+        std::env::set_var("MASKED_NOT_UNICODE", std::ffi::OsStr::from_bytes(b"hunter\xff"));
+        let err = Masked::<String>::load("MASKED_NOT_UNICODE").expect_err("Value should not be unicode");
+        set_verbose_errors(true);
+        assert_eq!(err.to_string(), "Error parsing environment variable 'MASKED_NOT_UNICODE': Not valid unicode");
+        assert!(!format!("{:?}", err).contains("hunter"));
+        set_verbose_errors(false);
+
+        let err = String::load("MASKED_NOT_UNICODE").expect_err("Value should not be unicode");
+        assert!(matches!(err.ty, EnvErrorType::NotUnicode(ref value) if value.as_encoded_bytes() == b"hunter\xff"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct SourcedConfig {
+        port: Sourced<u16>,
+        #[utils(var_or_file)]
+        host: Sourced<String>,
+        #[utils(name = "SOURCED_DB_NAME")]
+        database: Sourced<String>,
+        #[utils(trim)]
+        region: Sourced<String>,
+        #[utils(default = "5")]
+        pool: Sourced<u32>,
+        replicas: Option<Sourced<u8>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_sourced() {
+        std::env::set_var("SOURCED_PORT", "5432");
+        std::env::set_var("SOURCED_HOST", "db.local");
+        std::env::set_var("SOURCED_DB_NAME", "app");
+        std::env::set_var("SOURCED_REGION", " eu-west-1 ");
+        std::env::remove_var("SOURCED_POOL");
+        std::env::set_var("SOURCED_REPLICAS", "2");
+
+        let config = SourcedConfig::load("sourced").expect("Config should parse correctly");
+        assert_eq!(*config.port, 5432);
+        assert_eq!(config.port.var, "SOURCED_PORT");
+        assert_eq!(config.host.var, "SOURCED_HOST");
+        assert_eq!(config.database.len(), 3);
+        assert_eq!(config.database.var, "SOURCED_DB_NAME");
+        assert_eq!(*config.region, "eu-west-1");
+        assert_eq!(config.region.var, "SOURCED_REGION");
+        assert_eq!((*config.pool, config.pool.var.as_str()), (5, "SOURCED_POOL"));
+        assert_eq!(config.replicas.map(|replicas| replicas.var), Some(String::from("SOURCED_REPLICAS")));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct UpstreamCreds {
+        user: String,
+        key: Masked<u32>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct UpstreamConfig {
+        upstreams: Vec<SocketAddr>,
+        tokens: Option<Vec<Masked<u32>>>,
+        creds: Option<Vec<UpstreamCreds>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_list_element_errors() {
+        std::env::set_var("UPSTREAM_UPSTREAMS", "10.0.0.1:1,10.0.0.2:2,bad");
+        let err = UpstreamConfig::load("upstream").expect_err("Bad upstream should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Invalid element 'bad' at index 2: invalid socket address syntax"));
+
+        std::env::set_var("UPSTREAM_UPSTREAMS", "10.0.0.1:1");
+        std::env::set_var("UPSTREAM_TOKENS", "1234,s3cr3t");
+        let err = UpstreamConfig::load("upstream").expect_err("Bad token should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Invalid element at index 1: invalid digit found in string"));
+        assert_eq!(err.value, None);
+
+        // A derived element type with a Masked field is just as secret
+        std::env::remove_var("UPSTREAM_TOKENS");
+        std::env::set_var("UPSTREAM_CREDS", "user=a;key=1, user=b;key=s3cr3t");
+        let err = UpstreamConfig::load("upstream").expect_err("Bad creds should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.starts_with("Invalid element at index 1: ") && !message.contains("s3cr3t")));
+        assert_eq!(err.value, None);
+        std::env::remove_var("UPSTREAM_CREDS");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct InstanceShard {
+        host: String,
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct InstanceConfig {
+        #[utils(index_from = "INSTANCE_INDEX")]
+        shard: InstanceShard
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_index_from() {
+        std::env::remove_var("INSTANCE_INDEX");
+        let err = InstanceConfig::load("instance").expect_err("Missing index should error");
+        assert_eq!(err.var, "INSTANCE_INDEX");
+        assert!(matches!(err.ty, EnvErrorType::NotPresent));
+
+        std::env::set_var("INSTANCE_SHARD_2_HOST", "shard-2.local");
+        std::env::set_var("INSTANCE_SHARD_2_PORT", "5432");
+        std::env::set_var("INSTANCE_SHARD_3_HOST", "shard-3.local");
+        std::env::set_var("INSTANCE_SHARD_3_PORT", "5433");
+        std::env::set_var("INSTANCE_INDEX", "3");
+
+        let config = InstanceConfig::load("instance").expect("Config should parse correctly");
+        assert_eq!(config.shard.host, "shard-3.local");
+        assert_eq!(config.shard.port, 5433);
+        assert_eq!(InstanceConfig::env_vars("instance"), ["INSTANCE_INDEX", "INSTANCE_SHARD_3_HOST", "INSTANCE_SHARD_3_PORT"]);
+    }
+
+    #[test]
+    fn test_secret_warning() {
+        assert!(__secret_warning("api_token", false, SECRET_PATTERNS).is_some_and(|message| message.contains("api_token")));
+        assert_eq!(__secret_warning("api_token", true, SECRET_PATTERNS), None);
+        assert_eq!(__secret_warning("hostname", false, SECRET_PATTERNS), None);
+        assert!(__secret_warning("DSN", false, &["dsn"]).is_some());
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(check_secrets)]
+    #[allow(dead_code)]
+    struct WarnSecretsConfig {
+        api_token: String,
+        db_password: Masked<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct DenySecretsPassword(Masked<String>);
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(check_secrets = "error", secret_patterns = "dsn, password")]
+    #[allow(dead_code)]
+    struct DenySecretsConfig {
+        api_token: String,
+        // A newtype around Masked counts as masked
+        db_password: DenySecretsPassword,
+        sentry_dsn: Option<String>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_check_secrets() {
+        std::env::set_var("CHECK_SECRETS_API_TOKEN", "abc");
+        std::env::set_var("CHECK_SECRETS_DB_PASSWORD", "hunter2");
+
+        // Only warns, through tracing
+        assert!(WarnSecretsConfig::load("check_secrets").is_ok());
+
+        let err = DenySecretsConfig::load("check_secrets").expect_err("Unmasked dsn should error");
+        assert_eq!(err.var, "CHECK_SECRETS_SENTRY_DSN");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message.contains("sentry_dsn")));
+    }
+
+    #[test]
+    fn test_rate() {
+        let per_second = Rate::from_env("100/s").expect("Rate should parse");
+        let per_minute = Rate::from_env("6000/m").expect("Rate should parse");
+        assert_eq!(per_second.interval(), Duration::from_millis(10));
+        assert_eq!(per_second.interval(), per_minute.interval());
+        assert_eq!(per_minute.per_second(), 100.0);
+        assert_eq!(Rate::from_env("5/30s").expect("Rate should parse").interval(), Duration::from_secs(6));
+
+        // Display keeps the exact period, so every rate reads back the same
+        for value in ["100/s", "6000/m", "5/30s", "3/1500ms", "1/d", "7/172800s"] {
+            let rate = Rate::from_env(value).expect("Rate should parse");
+            assert_eq!(rate.to_string(), value);
+            assert_eq!(Rate::from_env(&rate.to_string()).ok(), Some(rate));
+        }
+        assert_eq!(Rate::new(2, Duration::from_secs(120)).expect("Rate should be valid").to_string(), "2/120s");
+
+        assert!(matches!(Rate::from_env("100/w"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(Rate::from_env("100"), Err(EnvErrorType::InvalidFormat)));
+        assert!(matches!(Rate::from_env("0/s"), Err(EnvErrorType::Other(_))));
+        assert!(matches!(Rate::from_env("1/0s"), Err(EnvErrorType::Other(_))));
+        assert!(Rate::new(0, Duration::from_secs(1)).is_none());
+        assert!(Rate::new(1, Duration::ZERO).is_none());
+    }
+
+    #[cfg(feature = "secrecy")]
+    #[test]
+    fn test_secrecy() {
+        use secrecy::ExposeSecret;
+
+        std::env::set_var("SECRECY_API_KEY", "hunter2");
+        let key = secrecy::SecretString::load("SECRECY_API_KEY").expect("Value should load");
+        assert_eq!(key.expose_secret(), "hunter2");
+        assert!(!format!("{:?}", key).contains("hunter2"));
+
+        std::env::set_var("SECRECY_SEED", "1,2,3");
+        let seed = secrecy::SecretSlice::<u8>::load("SECRECY_SEED").expect("Value should load");
+        assert_eq!(seed.expose_secret(), [1, 2, 3]);
+
+        std::env::set_var("SECRECY_SEED", "1,x");
+        assert_eq!(secrecy::SecretSlice::<u8>::load("SECRECY_SEED").expect_err("Bad element should error").value, None);
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct BlankConfig {
+        #[utils(blank_as_unset)]
+        name: String,
+        #[utils(blank_as_unset)]
+        region: Option<String>,
+        #[utils(blank_as_unset, default = "8080")]
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_blank_as_unset() {
+        std::env::set_var("BLANK_NAME", "frontend");
+        std::env::set_var("BLANK_REGION", "   ");
+        std::env::set_var("BLANK_PORT", " \t ");
+
+        let config = BlankConfig::load("blank").expect("Config should parse correctly");
+        assert_eq!(config.name, "frontend");
+        assert_eq!(config.region, None);
+        assert_eq!(config.port, 8080);
+
+        std::env::set_var("BLANK_NAME", "  ");
+        let err = BlankConfig::load("blank").expect_err("Blank required field should error");
+        assert_eq!(err.var, "BLANK_NAME");
+        assert!(matches!(err.ty, EnvErrorType::NotPresent));
+    }
+
+    #[test]
+    fn test_blank_as_unset_restored_on_panic() {
+        assert!(std::panic::catch_unwind(|| __blank_as_unset(|| panic!("load panicked"))).is_err());
+        assert!(!BLANK_AS_UNSET.with(|flag| flag.get()));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(separator = ";")]
+    struct CsvList(Vec<String>);
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct CsvConfig {
+        columns: CsvList
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_newtype_separator() {
+        std::env::set_var("CSV_COLUMNS", "id; name, email;created_at");
+        let config = CsvConfig::load("csv").expect("Config should parse correctly");
+        assert_eq!(config.columns.0, ["id", "name, email", "created_at"]);
+    }
+
+    #[cfg(all(feature = "derive", target_has_atomic = "8", target_has_atomic = "ptr"))]
+    #[derive(FromEnv, Debug)]
+    struct AtomicConfig {
+        maintenance: AtomicBool,
+        max_connections: AtomicUsize
+    }
+
+    #[cfg(all(feature = "derive", target_has_atomic = "8", target_has_atomic = "ptr"))]
+    #[test]
+    fn test_atomics() {
+        use std::sync::atomic::Ordering;
+
+        std::env::set_var("ATOMIC_MAINTENANCE", "on");
+        std::env::set_var("ATOMIC_MAX_CONNECTIONS", "64");
+        let config = AtomicConfig::load("atomic").expect("Config should parse correctly");
+        assert!(config.maintenance.load(Ordering::Relaxed));
+        assert_eq!(config.max_connections.load(Ordering::Relaxed), 64);
+
+        config.maintenance.store(false, Ordering::Relaxed);
+        assert!(!config.maintenance.load(Ordering::Relaxed));
+        assert!(matches!(AtomicU8::from_env("256"), Err(EnvErrorType::Parse(_))));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(global)]
+    struct GlobalConfig {
+        region: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_shared_and_global() {
+        std::env::set_var("SHARED_REGION", "eu-west-1");
+        let shared = GlobalConfig::load_shared("shared").expect("Config should parse correctly");
+        let handle = {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || shared.region.clone())
+        };
+        assert_eq!(handle.join().unwrap(), "eu-west-1");
+
+        std::env::remove_var("GLOBAL_REGION");
+        assert!(GlobalConfig::init_global("global").is_err());
+
+        std::env::set_var("GLOBAL_REGION", "us-east-1");
+        assert_eq!(GlobalConfig::init_global("global").expect("Config should parse correctly").region, "us-east-1");
+        assert_eq!(GlobalConfig::init_global("shared").expect("Already loaded").region, "us-east-1");
+        assert_eq!(std::thread::spawn(|| GlobalConfig::global().region.as_str()).join().unwrap(), "us-east-1");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[utils(from_bool)]
+    enum Mode {
+        #[utils(truthy)]
+        Enabled,
+        #[utils(falsey)]
+        Disabled
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_enum_from_bool() {
+        assert_eq!(Mode::from_env("true").ok(), Some(Mode::Enabled));
+        assert_eq!(Mode::from_env("ON").ok(), Some(Mode::Enabled));
+        assert_eq!(Mode::from_env("false").ok(), Some(Mode::Disabled));
+        assert_eq!(Mode::from_env("0").ok(), Some(Mode::Disabled));
+        assert!(matches!(Mode::from_env("enabled-ish"), Err(EnvErrorType::InvalidFormat)));
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    struct LayeredDb {
+        host: String,
+        port: u16
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    struct LayeredConfig {
+        name: String,
+        db: LayeredDb,
+        tags: Vec<String>,
+        #[utils(default = "info")]
+        level: String
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_load_layered() {
+        let path = std::env::temp_dir().join("utils_layered_config.json");
+        std::fs::write(&path, r#"{"name": "from-file", "db": {"host": "db.file", "port": 5432}, "tags": ["a", "b"]}"#).expect("Temp file should be writable");
+
+        std::env::set_var("LAYERED_DB_PORT", "6543");
+        let config: LayeredConfig = load_layered(&path, "layered").expect("Config should parse correctly");
+        assert_eq!(config.name, "from-file");
+        assert_eq!(config.db.host, "db.file");
+        assert_eq!(config.db.port, 6543);
+        assert_eq!(config.tags, ["a", "b"]);
+        assert_eq!(config.level, "info");
+
+        let err = load_layered::<LayeredConfig>(std::env::temp_dir().join("utils_layered_missing.json"), "layered").expect_err("Without the file name is missing");
+        assert_eq!(err.var, "LAYERED_NAME");
+
+        // An enclosing source takes the place of the environment
+        let source = HashMap::from([(String::from("LAYERED_NAME"), String::from("from-source"))]);
+        let config: LayeredConfig = __with_source(Arc::new(source), || load_layered(&path, "layered")).expect("Config should parse correctly");
+        assert_eq!(config.name, "from-source");
+        assert_eq!(config.db.port, 5432);
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde", feature = "dirs", target_os = "linux"))]
+    #[test]
+    fn test_load_layered_config() {
+        let dir = std::env::temp_dir().join("utils_layered_config_home");
+        std::fs::create_dir_all(dir.join("layered_app")).expect("Temp dir should be writable");
+        std::fs::write(dir.join("layered_app").join("config.json"), r#"{"name": "app", "db": {"host": "db.file", "port": 5432}, "tags": []}"#).expect("Temp file should be writable");
+
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+        std::env::set_var("LAYERED_APP_DB_HOST", "db.env");
+        let config: LayeredConfig = load_layered_config("layered_app").expect("Config should parse correctly");
+        assert_eq!(config.name, "app");
+        assert_eq!(config.db.host, "db.env");
+        assert_eq!(config.db.port, 5432);
+        assert!(config.tags.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct AllowedConfig {
+        #[utils(allowed = ["info", "warn"])]
+        log_level: LogLevel,
+        #[utils(allowed = ["info"])]
+        audit_level: Option<LogLevel>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct AllowedTypoConfig {
+        #[utils(allowed = ["info", "wran"])]
+        log_level: LogLevel
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_allowed() {
+        std::env::set_var("ALLOWED_LOG_LEVEL", "WARN");
+        let config = AllowedConfig::load("allowed").expect("Config should parse correctly");
+        assert_eq!(config.log_level, LogLevel::Warn);
+        assert_eq!(config.audit_level, None);
+
+        std::env::set_var("ALLOWED_LOG_LEVEL", "debug");
+        let err = AllowedConfig::load("allowed").expect_err("Disallowed level should error");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Must be one of 'info', 'warn'"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_allowed_typo() {
+        // Even a value the list allows doesn't hide the typo
+        std::env::set_var("ALLOWED_TYPO_LOG_LEVEL", "info");
+        let err = AllowedTypoConfig::load("allowed_typo").expect_err("Typo in the allowed list should error");
+        assert_eq!(err.var, "ALLOWED_TYPO_LOG_LEVEL");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "#[utils(allowed)] lists 'wran', which isn't a valid LogLevel"));
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    struct FileOnlyConfig {
+        #[utils(file_only)]
+        token: Masked<String>,
+        #[utils(file_only)]
+        backup_token: Option<Masked<String>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_file_only() {
+        let path = std::env::temp_dir().join("utils_file_only_token.txt");
+        std::fs::write(&path, "secret\n").expect("Temp file should be writable");
+
+        std::env::set_var("FILE_ONLY_TOKEN_FILE", &path);
+        let config = FileOnlyConfig::load("file_only").expect("Config should parse correctly");
+        assert_eq!(config.token.0, "secret");
+        assert!(config.backup_token.is_none());
+
+        std::env::set_var("FILE_ONLY_BACKUP_TOKEN", "hunter2");
+        let err = FileOnlyConfig::load("file_only").expect_err("Plain var should be rejected");
+        assert_eq!(err.var, "FILE_ONLY_BACKUP_TOKEN");
+        assert!(matches!(err.ty, EnvErrorType::Other(ref message) if message == "Has to be given as a file through FILE_ONLY_BACKUP_TOKEN_FILE, a plain value isn't accepted"));
+
+        // Only the _FILE var is advertised
+        assert_eq!(FileOnlyConfig::env_vars("file_only"), ["FILE_ONLY_TOKEN_FILE", "FILE_ONLY_BACKUP_TOKEN_FILE"]);
+        assert_eq!(FileOnlyConfig::help("file_only"), "FILE_ONLY_TOKEN_FILE (required)\nFILE_ONLY_BACKUP_TOKEN_FILE (optional)");
+        #[cfg(feature = "serde")]
+        assert_eq!(FileOnlyConfig::json_schema("file_only")["properties"]["token"]["x-env-var"], "FILE_ONLY_TOKEN_FILE");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct SchemaDatabase {
+        /// Host the database listens on
+        host: String,
+        #[utils(default = "5432")]
+        port: u16,
+        password: Masked<String>,
+        enabled: bool
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct SchemaConfig {
+        /// Name shown in logs,
+        /// defaults to the binary name
+        name: Option<String>,
+        db: SchemaDatabase
+    }
+
+    #[cfg(all(feature = "derive", feature = "serde"))]
+    #[test]
+    fn test_json_schema() {
+        let schema = SchemaConfig::json_schema("schema");
+        assert_eq!(schema["$schema"], "https://json-schema.org/draft/2020-12/schema");
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["required"], serde_json::json!(["db"]));
+
+        let name = &schema["properties"]["name"];
+        assert_eq!(name["x-env-var"], "SCHEMA_NAME");
+        assert_eq!(name["type"], "string");
+        assert_eq!(name["description"], "Name shown in logs, defaults to the binary name");
+
+        let db = &schema["properties"]["db"];
+        assert_eq!(db["type"], "object");
+        assert!(db.get("$schema").is_none());
+        assert_eq!(db["required"], serde_json::json!(["host", "password", "enabled"]));
+        assert_eq!(db["properties"]["host"], serde_json::json!({ "type": "string", "x-env-var": "SCHEMA_DB_HOST", "description": "Host the database listens on" }));
+        assert_eq!(db["properties"]["port"], serde_json::json!({ "type": "integer", "x-env-var": "SCHEMA_DB_PORT", "default": 5432 }));
+        assert_eq!(db["properties"]["password"], serde_json::json!({ "type": "string", "x-env-var": "SCHEMA_DB_PASSWORD", "writeOnly": true }));
+        assert_eq!(db["properties"]["enabled"]["type"], "boolean");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(instances)]
+    struct PoolUpstream {
+        host: String,
+        #[utils(default = "80")]
+        port: u16
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_all_instances() {
+        std::env::set_var("UPSTREAMS_EU_HOST", "eu.example.com");
+        std::env::set_var("UPSTREAMS_US_HOST", "us.example.com");
+        std::env::set_var("UPSTREAMS_US_PORT", "8080");
+
+        let upstreams = PoolUpstream::load_all_instances("upstreams").expect("Instances should parse correctly");
+        assert_eq!(upstreams.len(), 2);
+        assert_eq!(upstreams["EU"].host, "eu.example.com");
+        assert_eq!(upstreams["EU"].port, 80);
+        assert_eq!(upstreams["US"].host, "us.example.com");
+        assert_eq!(upstreams["US"].port, 8080);
+
+        // Set, but not one of PoolUpstream's vars
+        std::env::set_var("UPSTREAMS_DEBUG_X", "1");
+        let upstreams = PoolUpstream::load_all_instances("upstreams").expect("Unrelated vars should be skipped");
+        assert_eq!(upstreams.len(), 2);
+
+        std::env::set_var("UPSTREAMS_ASIA_PORT", "8080");
+        let err = PoolUpstream::load_all_instances("upstreams").expect_err("Instance without a host should error");
+        assert_eq!(err.var, "UPSTREAMS_ASIA_HOST");
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[utils(case_insensitive, instances)]
+    struct CaseUpstream {
+        host: String
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_load_all_instances_case_insensitive() {
+        let source = HashMap::from([
+            (String::from("ci_upstreams_eu_host"), String::from("eu.example.com")),
+            (String::from("CI_UPSTREAMS_US_HOST"), String::from("us.example.com"))
+        ]);
+
+        let upstreams = __with_source(Arc::new(source), || CaseUpstream::load_all_instances("ci_upstreams"))
+            .expect("Instances should match in any case");
+        assert_eq!(upstreams.len(), 2);
+        assert_eq!(upstreams["eu"].host, "eu.example.com");
+        assert_eq!(upstreams["US"].host, "us.example.com");
+    }
+
+    #[cfg(all(feature = "derive", feature = "async"))]
+    #[derive(FromEnv, Debug)]
+    struct AsyncSecrets {
+        #[utils(var_or_file)]
+        api_key: Masked<String>,
+        #[utils(var_or_file)]
+        db_password: Masked<String>,
+        #[utils(file_only)]
+        signing_key: Masked<String>,
+        region: String
+    }
+
+    #[cfg(all(feature = "derive", feature = "async"))]
+    #[test]
+    fn test_load_async() {
+        let dir = std::env::temp_dir();
+        for (var, file, contents) in [("ASYNC_API_KEY_FILE", "utils_async_api_key.txt", "key"), ("ASYNC_DB_PASSWORD_FILE", "utils_async_db_password.txt", "hunter2"), ("ASYNC_SIGNING_KEY_FILE", "utils_async_signing_key.txt", "signing\n")] {
+            std::fs::write(dir.join(file), contents).expect("Temp file should be writable");
+            std::env::set_var(var, dir.join(file));
+        }
+        std::env::set_var("ASYNC_REGION", "eu-west-1");
+
+        let config: AsyncSecrets = futures_executor::block_on(load_async("async")).expect("Config should parse correctly");
+        assert_eq!(config.api_key.0, "key");
+        assert_eq!(config.db_password.0, "hunter2");
+        assert_eq!(config.signing_key.0, "signing");
+        assert_eq!(config.region, "eu-west-1");
+
+        std::env::set_var("ASYNC_DB_PASSWORD_FILE", dir.join("utils_async_missing.txt"));
+        std::env::remove_var("ASYNC_REGION");
+        let err = futures_executor::block_on(load_async::<AsyncSecrets>("async")).expect_err("Missing file should error");
+        assert_eq!(err.var, "ASYNC_DB_PASSWORD_FILE");
+
+        let errs = futures_executor::block_on(load_all_async::<AsyncSecrets>("async")).expect_err("Both errors should be collected");
+        assert_eq!(errs.0.len(), 2);
+
+        for file in ["utils_async_api_key.txt", "utils_async_db_password.txt", "utils_async_signing_key.txt"] {
+            std::fs::remove_file(dir.join(file)).unwrap();
+        }
+    }
+
+    #[cfg(all(feature = "derive", feature = "async"))]
+    #[derive(FromEnv, Debug)]
+    struct PrefetchedConfig {
+        #[utils(var_or_file)]
+        token: String,
+        #[utils(var_or_file, read_timeout = "1s")]
+        slow_token: String
+    }
+
+    #[cfg(all(feature = "derive", feature = "async"))]
+    #[test]
+    fn test_prefetched_files() {
+        let dir = std::env::temp_dir();
+        let (token, slow_token) = (dir.join("utils_prefetched_token.txt"), dir.join("utils_prefetched_slow_token.txt"));
+        std::fs::write(&token, "disk").expect("Temp file should be writable");
+        std::fs::write(&slow_token, "disk").expect("Temp file should be writable");
+        std::env::remove_var("PREFETCHED_TOKEN");
+        std::env::remove_var("PREFETCHED_SLOW_TOKEN");
+        std::env::set_var("PREFETCHED_TOKEN_FILE", &token);
+        std::env::set_var("PREFETCHED_SLOW_TOKEN_FILE", &slow_token);
+
+        let files = futures_executor::block_on(__prefetch_files::<PrefetchedConfig>("prefetched"));
+        assert_eq!(files.len(), 2);
+
+        // The load takes what was prefetched rather than reading the files again, on the thread
+        // of a read_timeout field too
+        let files = files.into_keys().map(|path| (path, String::from("memory"))).collect();
+        let config = __with_prefetched(files, || PrefetchedConfig::load("prefetched")).expect("Config should parse correctly");
+        assert_eq!((config.token.as_str(), config.slow_token.as_str()), ("memory", "memory"));
+        assert!(PREFETCHED.with(|current| current.borrow().is_none()));
+
+        // A panicking load doesn't leave its files behind for the next one
+        assert!(std::panic::catch_unwind(|| __with_prefetched(HashMap::new(), || panic!("load panicked"))).is_err());
+        assert!(PREFETCHED.with(|current| current.borrow().is_none()));
+
+        std::fs::remove_file(&token).unwrap();
+        std::fs::remove_file(&slow_token).unwrap();
+    }
+
+    #[cfg(all(feature = "derive", feature = "async"))]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct PrefetchedPipes {
+        #[utils(var_or_file)]
+        first: String,
+        #[utils(var_or_file)]
+        second: String
+    }
+
+    #[cfg(all(feature = "derive", feature = "async", unix))]
+    #[test]
+    fn test_prefetch_concurrently() {
+        // Opening a FIFO blocks until the other end is opened too, and neither writer writes until
+        // both pipes are open. Reading one pipe after the other never finishes.
+        let dir = std::env::temp_dir();
+        let (first, second) = (dir.join("utils_prefetch_first.fifo"), dir.join("utils_prefetch_second.fifo"));
+        for pipe in [&first, &second] {
+            let _ = std::fs::remove_file(pipe);
+            assert!(std::process::Command::new("mkfifo").arg(pipe).status().expect("mkfifo should run").success());
+        }
+        std::env::remove_var("PREFETCH_PIPES_FIRST");
+        std::env::remove_var("PREFETCH_PIPES_SECOND");
+        std::env::set_var("PREFETCH_PIPES_FIRST_FILE", &first);
+        std::env::set_var("PREFETCH_PIPES_SECOND_FILE", &second);
+
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        for (pipe, contents) in [(first.clone(), "1"), (second.clone(), "2")] {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                let mut pipe = std::fs::OpenOptions::new().write(true).open(pipe).unwrap();
+                barrier.wait();
+                std::io::Write::write_all(&mut pipe, contents.as_bytes()).unwrap();
+            });
+        }
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || sender.send(futures_executor::block_on(__prefetch_files::<PrefetchedPipes>("prefetch_pipes"))));
+        let files = receiver.recv_timeout(Duration::from_secs(5)).expect("Files should be read concurrently");
+        assert_eq!(files[first.to_str().unwrap()], "1");
+        assert_eq!(files[second.to_str().unwrap()], "2");
+
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct SecretPin(Masked<u16>);
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct SecretBlobCreds {
+        user: String,
+        key: Masked<u16>
+    }
+
+    #[cfg(feature = "derive")]
+    #[derive(FromEnv, Debug)]
+    #[allow(dead_code)]
+    struct SecretValuesConfig {
+        pin: Option<SecretPin>,
+        creds: Option<Vec<SecretBlobCreds>>,
+        keys: Option<HashMap<String, Masked<u16>>>
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_secret_error_values() {
+        assert!(SecretPin::is_secret());
+        assert!(SecretBlobCreds::is_secret());
+        assert!(SecretCredentials::is_secret());
+        assert!(HashMap::<String, Masked<u16>>::is_secret());
+        assert!(!HashMap::<String, u16>::is_secret());
+
+        for (var, value) in [("SECRET_VALUES_PIN", "12ab"), ("SECRET_VALUES_CREDS", "user=a;key=12ab"), ("SECRET_VALUES_KEYS", "a=12ab")] {
+            std::env::set_var(var, value);
+            let err = SecretValuesConfig::load("secret_values").expect_err("Invalid secret should error");
+            assert_eq!(err.var, var);
+            assert_eq!(err.value, None);
+            std::env::remove_var(var);
+        }
+
+        // Every field of a secret container is masked, String ones included
+        std::env::set_var("SECRET_ERRORS_USER", "admin");
+        std::env::set_var("SECRET_ERRORS_KEY_ID", "hunter2");
+        let err = SecretCredentials::load("secret_errors").expect_err("Invalid key id should error");
+        assert_eq!(err.value, None);
+
+        // Debug leaves the value out unless errors are verbose
+        std::env::set_var("SECRET_ERRORS_PORT", "http");
+        let err = u16::load("SECRET_ERRORS_PORT").expect_err("Invalid port should error");
+        assert!(!format!("{:?}", err).contains("http"));
+        set_verbose_errors(true);
+        assert!(format!("{:?}", err).contains("http"));
+        set_verbose_errors(false);
     }
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+#[cfg(test)]
+mod test {
+    use envkit::{EnvErrorType, FromEnv};
+
+    // A local module called `utils`, user-supplied paths into it must be left alone
+    mod utils {
+        pub fn parse_port(value: &str) -> Result<u16, envkit::EnvErrorType> {
+            value.trim_start_matches(':').parse().map_err(|_| envkit::EnvErrorType::InvalidFormat)
+        }
+    }
+
+    #[derive(FromEnv, Debug, PartialEq)]
+    #[utils(crate = "envkit")]
+    enum Level {
+        Info,
+        Debug
+    }
+
+    #[derive(FromEnv, Debug)]
+    #[utils(crate = "envkit")]
+    struct RenamedConfig {
+        #[utils(parse_with = utils::parse_port)]
+        port: u16,
+        #[utils(default = "info")]
+        level: Level,
+        replicas: Option<u8>
+    }
+
+    #[test]
+    fn test_crate_path() {
+        std::env::set_var("RENAMED_PORT", ":8080");
+        let config = RenamedConfig::load("renamed").expect("Config should parse correctly");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.level, Level::Info);
+        assert_eq!(config.replicas, None);
+        assert_eq!(RenamedConfig::env_vars("renamed"), ["RENAMED_PORT", "RENAMED_LEVEL", "RENAMED_REPLICAS"]);
+
+        std::env::set_var("RENAMED_PORT", "http");
+        let err = RenamedConfig::load("renamed").expect_err("Invalid port should error");
+        assert!(matches!(err.ty, EnvErrorType::InvalidFormat));
+    }
+}